@@ -1,88 +1,130 @@
 
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::{process};
-use crate::interpreter::runtime_types::{History, HistoryCollection, SharedHistory, VariableType};
+use crate::interpreter::runtime_types::{Argument, History, HistoryCollection, Passing, SharedHistory, VariableType};
 use crate::interpreter::parser::parsing_types::{Token, TokenType};
 use crate::interpreter::parser::lexer::symbol_analysis;
-use crate::user_options::USER_OPTIONS;
+use crate::user_options::{MAX_DEPTH_LIMIT, USER_OPTIONS};
 
 pub struct ArgResult {
     pub file_name: String,
-    pub parameters: Option<HistoryCollection>,
+    pub parameters: Vec<String>, // as given, turned into histories by get_parameters
+    pub test_dir: Option<String>, // set by sequence test [dir]
 }
 
 pub fn handle_args(args: &Vec<String>) -> ArgResult {
     let mut result: ArgResult = ArgResult {
         file_name: String::new(),
-        parameters: None,
+        parameters: Vec::new(),
+        test_dir: None,
     };
 
     if args.len() < 2 {
         usage(&args[0])
     };
 
-    let mut paramter_index = 0;
-    for i in 1..args.len() {
+    let mut i = 1;
+    while i < args.len() {
         if args[i].starts_with("-") {
             match args[i].as_str() {
                 "-d" => USER_OPTIONS.lock().unwrap().debug = true,
+                "-I" => {
+                    i += 1;
+                    if i >= args.len() {
+                        usage(&args[0]);
+                    }
+                    USER_OPTIONS.lock().unwrap().include_dirs.push(PathBuf::from(&args[i]));
+                },
+                "--max-depth" => {
+                    i += 1;
+                    match args.get(i).and_then(|x| x.parse().ok()) {
+                        Some(depth) if depth <= MAX_DEPTH_LIMIT => USER_OPTIONS.lock().unwrap().max_depth = depth,
+                        _ => usage(&args[0]),
+                    }
+                },
                 _ => {},
             }
+            i += 1;
             continue;
         }
 
+        if args[i] == "test" {
+            result.test_dir = Some(args.get(i + 1).cloned().unwrap_or(String::from(".")));
+            return result;
+        }
+
         result.file_name = args[i].clone();
-        paramter_index = i + 1;
+        result.parameters = args[i + 1..].to_vec();
         break;
     }
 
-    result.parameters = get_parameters(args, paramter_index);
-
     return result;
 }
 
-fn get_parameters(args: &Vec<String>, index: usize) -> Option<HistoryCollection> {
-    if index >= args.len() {
+// histories aren't Send, so the parameters are only turned into them on the thread that runs the program
+pub fn get_parameters(args: &Vec<String>) -> Option<HistoryCollection> {
+    if args.is_empty() {
         return None; 
     }
 
     let mut histories: HistoryCollection = HistoryCollection::new();
-    for i in index..args.len() {
+    for arg in args {
+        // name={1, 2} binds to the parameter called name instead of the next one
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) if !name.starts_with('{') => (Some(name.trim().to_string()), value),
+            _ => (None, arg.as_str()),
+        };
 
-        let tokens: Option<Vec<Token>>= symbol_analysis(args[i].as_str());
+        let tokens: Option<Vec<Token>>= symbol_analysis(value);
         let history = parse_history(tokens.unwrap());
         
-        histories.push(history);
+        // parameters from the command line are treated like histories of the caller
+        histories.push(Argument { name, history, passing: Passing::NAME });
     }
 
-    return Some(histories);
+    Some(histories)
 }
 
 // history syntax 
-// {1, 2, 3, 4, 10}
+// {1, 2, 3, 4, 10} or name={1, 2, 3, 4, 10}
 fn parse_history(tokens: Vec<Token>) -> SharedHistory { 
     let mut history = History::new();
 
     assert!(tokens[0].token_type == TokenType::LBRACKET);
 
-    for i in (1..tokens.len()).step_by(2) { 
+    let mut i = 1;
+    while i < tokens.len() {
+        let negative = tokens[i].token_type == TokenType::SUBOP;
+        if negative {
+            i += 1;
+        }
+        let sign = if negative { -1 } else { 1 };
         match tokens[i].token_type {
-            TokenType::FLOAT(x) => history.add(VariableType::FLOAT(x)),
-            TokenType::INTEGER(x) => history.add(VariableType::INTEGER(x)),
+            TokenType::FLOAT(x) => history.add(VariableType::FLOAT(sign as f64 * x)),
+            TokenType::INTEGER(x) => history.add(VariableType::INTEGER(sign * x)),
+            TokenType::TRUE if !negative => history.add(VariableType::BOOL(true)),
+            TokenType::FALSE if !negative => history.add(VariableType::BOOL(false)),
             _ => panic!("bad token in parameter {:?}", tokens[i].token_type),
         };
 
         assert!(tokens[i+1].token_type == TokenType::COMMA || tokens[i+1].token_type == TokenType::RBRACKET);
+        i += 2;
     }
     assert!(tokens[tokens.len()-1].token_type == TokenType::RBRACKET);
-    return Rc::new(RefCell::new(history));
+    Rc::new(RefCell::new(history))
 }
 
 
 fn usage(progname: &String) {
     eprintln!("Usage:");
-    eprintln!("  {progname} [-d] <source> [parameters]6");
+    eprintln!("  {progname} [-d] [-I dir] [--max-depth n] <source> [parameters]");
+    eprintln!("  {progname} [-I dir] test [dir]");
+    eprintln!("  parameters: {{1, 2, 3}} in order, or name={{1, 2, 3}} by name");
+    eprintln!("  test [dir]: run the test blocks in the .sq files under dir, . by default");
     eprintln!("  -d: debug print");
+    eprintln!("  -I dir: also look for files in dir, can be repeated");
+    eprintln!("  --max-depth n: how deep programs can run each other, 200 by default and at most {MAX_DEPTH_LIMIT}");
     process::exit(1);
 }
\ No newline at end of file
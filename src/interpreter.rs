@@ -1,5 +1,15 @@
+// raises a RuntimeError, with a message formatted the way panic! formats one
+macro_rules! runtime_error {
+    ($($arg:tt)*) => {
+        std::panic::panic_any(crate::interpreter::runtime_types::RuntimeError(format!($($arg)*)))
+    };
+}
+
 pub mod executor;
 pub mod runtime_types;
 pub mod parser;
+mod builtins;
+mod stdlib;
+mod window;
 mod interpreter;
 mod test; 
\ No newline at end of file
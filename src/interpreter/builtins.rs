@@ -0,0 +1,175 @@
+use std::rc::Rc;
+
+use super::interpreter::{calculate_expression, window_size};
+use super::parser::expr::{Expression, ExpressionType};
+use super::runtime_types::{History, Memory, SharedHistory, VariableType};
+use super::window::{number, Window};
+
+// functions that can be called as name(args) inside an expression
+const BUILTINS: [&str; 12] = [
+    "keys", "size", "written_at", "written_by", "mean", "min", "max", "sum", "stddev", "converged",
+    "oscillating", "ancestor",
+];
+
+// the longest cycle oscillating looks for, so it only ever looks at the last values of a history
+const MAX_PERIOD: usize = 16;
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
+pub fn call_builtin(name: &str, args: &[Box<Expression>], memory: &mut Memory) -> VariableType {
+    match name {
+        // like other histories, the keys of m evaluate to the last of them
+        "keys" => {
+            let keys = keys(args, memory);
+            if keys.len() == 0 {
+                runtime_error!("keys: {} is an empty map", args[0]);
+            }
+            keys.get_past(keys.len() - 1)
+        }
+
+        // size(m): number of keys in m
+        "size" => {
+            expect_args(name, args, 1);
+            match calculate_expression(args[0].clone(), memory) {
+                VariableType::MAP(m) => VariableType::INTEGER(m.len() as i64),
+                x => runtime_error!("size expects a map, got {}", x),
+            }
+        }
+
+        // written_at(a, i) and written_by(a, i): the iteration in which, and the program by
+        // which, the i-th value of a (counting from the first) was appended
+        "written_at" | "written_by" => {
+            expect_args(name, args, 2);
+            let history = history_arg(name, &args[0], memory);
+            let index = calculate_expression(args[1].clone(), memory).convert_int();
+            let history = history.borrow();
+            let stamp = match index {
+                VariableType::INTEGER(i) if i >= 0 && (i as usize) < history.len() => {
+                    history.get_stamp(i as usize)
+                }
+                x => runtime_error!("{}: index {} out of range for history of size {}", name, x, history.len()),
+            };
+            if name == "written_at" {
+                VariableType::INTEGER(stamp.iteration as i64)
+            } else {
+                VariableType::STRING(stamp.program.to_string())
+            }
+        }
+
+        // statistics over the last n values of a history, as in mean(window a 5)
+        "mean" | "min" | "max" | "sum" | "stddev" => {
+            expect_args(name, args, 1);
+            let (id, history_name, size) = match &args[0].exp_type {
+                ExpressionType::WINDOW(id) => {
+                    (*id, args[0].var_name.clone().unwrap(), args[0].lhs.clone().unwrap())
+                }
+                _ => runtime_error!("{} expects a window, as in {}(window a 5)", name, name),
+            };
+            let size = window_size(size, memory);
+            let history = memory.get_history(history_name.clone());
+            let history = history.borrow();
+            if history.len() == 0 {
+                runtime_error!("{}({}): {} has no values", name, args[0], history_name);
+            }
+
+            let window = memory.windows.entry(id).or_insert_with(|| Window::new(size));
+            if !window.follows(&history, size) {
+                *window = Window::new(size);
+            }
+            window.update(&history);
+            match name {
+                "mean" => window.mean(),
+                "min" => window.min(&history),
+                "max" => window.max(&history),
+                "sum" => window.sum(),
+                _ => window.stddev(),
+            }
+        }
+
+        // converged(a, eps) and converged(a, eps, n): the last n steps of a (1 if not given)
+        // each changed it by at most eps, false while a has too few values to tell
+        "converged" => {
+            if args.len() != 2 && args.len() != 3 {
+                runtime_error!("converged expects 2 or 3 arguments, got {}", args.len());
+            }
+            let history = history_arg(name, &args[0], memory);
+            let eps = number(&calculate_expression(args[1].clone(), memory));
+            let steps = match args.get(2) {
+                Some(n) => match calculate_expression(n.clone(), memory).convert_int() {
+                    VariableType::INTEGER(n) if n >= 1 => n as usize,
+                    x => runtime_error!("converged: the number of steps must be at least 1, got {}", x),
+                },
+                None => 1,
+            };
+            let history = history.borrow();
+            if history.len() <= steps {
+                return VariableType::BOOL(false);
+            }
+            let last = history.len() - 1;
+            let settled = (0..steps).all(|i| {
+                let step = number(&history.get_past(last - i)) - number(&history.get_past(last - i - 1));
+                step.abs() <= eps
+            });
+            VariableType::BOOL(settled)
+        }
+
+        // oscillating(a): the last values of a are the same cycle twice in a row, so a keeps
+        // going round them instead of settling. only cycles of up to MAX_PERIOD values are looked for
+        "oscillating" => {
+            expect_args(name, args, 1);
+            let history = history_arg(name, &args[0], memory);
+            let history = history.borrow();
+            let len = history.len();
+            let cycles = (2..=MAX_PERIOD).take_while(|period| 2 * period <= len).any(|period| {
+                let repeats = (len - period..len).all(|i| history.get_past(i) == history.get_past(i - period));
+                // a value repeated over and over has settled
+                let settled = (len - period..len - 1).all(|i| history.get_past(i) == history.get_past(i + 1));
+                repeats && !settled
+            });
+            VariableType::BOOL(cycles)
+        }
+
+        // ancestor(a, b): the index of the last value a and b share because one was forked from
+        // the other (or both from the same history), -1 if they share none
+        "ancestor" => {
+            expect_args(name, args, 2);
+            let a = history_arg(name, &args[0], memory);
+            let b = history_arg(name, &args[1], memory);
+            if Rc::ptr_eq(&a, &b) {
+                return VariableType::INTEGER(a.borrow().len() as i64 - 1);
+            }
+            let shared = a.borrow().shared_with(&b.borrow());
+            VariableType::INTEGER(shared as i64 - 1)
+        }
+
+        _ => runtime_error!("unknown function {}", name),
+    }
+}
+
+// keys(m): a history of the keys of m in sorted order, used to loop over a map
+pub fn keys(args: &[Box<Expression>], memory: &mut Memory) -> History {
+    expect_args("keys", args, 1);
+    let map = calculate_expression(args[0].clone(), memory);
+    let mut keys = History::new();
+    match &map {
+        VariableType::MAP(m) => m.keys().for_each(|key| keys.add(key.to_variable())),
+        _ => runtime_error!("keys expects a map, got {}", map),
+    }
+    keys
+}
+
+// functions that look at a history rather than its value take its name
+fn history_arg(name: &str, arg: &Expression, memory: &Memory) -> SharedHistory {
+    match &arg.exp_type {
+        ExpressionType::IDENTIFIER(s) => memory.get_history(s.clone()),
+        _ => runtime_error!("{} expects the name of a history", name),
+    }
+}
+
+fn expect_args(name: &str, args: &[Box<Expression>], count: usize) {
+    if args.len() != count {
+        runtime_error!("{} expects {} arguments, got {}", name, count, args.len());
+    }
+}
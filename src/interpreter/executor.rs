@@ -1,19 +1,135 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::fs;
+use std::panic;
 use std::path::PathBuf;
+use std::rc::Rc;
 use super::interpreter;
 use super::parser;
-use super::parser::parse;
-use super::parser::statement::Program;
-use super::runtime_types::HistoryCollection;
+use super::parser::parse::{self, cache_key};
+use super::parser::parsing_types::TokenType;
+use super::parser::statement::{Program, ProgramCache};
+use super::runtime_types::{error_message, HistoryCollection, Memory};
 // this manages storing programs that correspond to specific files 
 // so they do have to be reevaluated each time the file is run
 
-pub fn run_program(input: &str, directory: &PathBuf, parameters: Option<HistoryCollection>) {
-    let tokens = parser::lexer::symbol_analysis(input).unwrap(); // better errors later
-    let mut prog_cache: HashMap<String, Box<Program>> = HashMap::new(); 
+// parses source as the file at path and adds it to the cache. a host can preload programs this
+// way before running one, and runs of that path will use them without the file having to exist
+pub fn preload(programs: &mut ProgramCache, path: &PathBuf, source: &str) -> Rc<Program> {
+    let tokens = parser::lexer::symbol_analysis(source).unwrap(); // better errors later
+    let prog = {
+        let mut parser = parse::Parser::new(tokens, programs, path);
+        Rc::new(parser.run().clone())
+    };
+    programs.insert(cache_key(path), prog.clone());
+    prog
+}
 
-    let mut parser = parse::Parser::new(tokens, &mut prog_cache, directory, true);
-    let prog = parser.run(); 
+// runs the program at path, which has to be in the cache already. the cache is shared with every
+// program it runs, so each file is parsed at most once
+pub fn run_program(programs: ProgramCache, path: &PathBuf, parameters: Option<HistoryCollection>) {
+    let prog: Rc<Program> = match programs.get(&cache_key(path)) {
+        Some(prog) => prog.clone(),
+        None => panic!("{} has not been loaded", path.to_string_lossy()),
+    };
 
-    interpreter::execute_program(prog, None, parameters); 
-}
\ No newline at end of file
+    let mut memory = Memory::new();
+    memory.programs = Rc::new(RefCell::new(programs));
+    let mut memory = memory.child(&prog.name);
+    memory.top_level = true;
+    interpreter::execute_program(&prog, Some(memory), parameters);
+}
+
+// shows a runtime error or violation the program doesn't catch as its message alone. any other
+// panic is a bug in the interpreter and gets the usual panic output
+pub fn report_errors() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| match error_message(info.payload()) {
+        Some(_) if interpreter::is_caught(info.payload()) => {}
+        Some(message) => eprintln!("{}", message),
+        None => default(info),
+    }));
+}
+
+// runs every test block in the .sq files under dir, each in memory of its own, and reports how
+// many passed. returns whether all of them did
+pub fn run_tests(dir: &PathBuf) -> bool {
+    let mut files = vec![];
+    find_sources(dir, &mut files);
+    files.sort();
+
+    // only files with tests are parsed, the rest may be programs that need parameters
+    let mut programs = ProgramCache::new();
+    let mut tested = vec![];
+    for path in files {
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        // test "name" { ... }, test being a name anywhere else
+        let has_tests = parser::lexer::symbol_analysis(&source).is_some_and(|tokens| {
+            tokens.windows(2).any(|pair| match (&pair[0].token_type, &pair[1].token_type) {
+                (TokenType::IDENTIFIER(word), TokenType::STRING(_)) => word.eq_ignore_ascii_case("test"),
+                _ => false,
+            })
+        });
+        if !has_tests {
+            continue;
+        }
+        // a file that doesn't parse fails as a whole, and the other files are still tested
+        let prog = interpreter::catch_error(|| preload(&mut programs, &path, &source));
+        tested.push((path, prog));
+    }
+
+    let programs = Rc::new(RefCell::new(programs));
+    let (mut passed, mut failed) = (0, 0);
+    for (path, prog) in tested {
+        let prog = match prog {
+            Ok(prog) => prog,
+            Err(message) => {
+                failed += 1;
+                println!("FAIL {}", path.display());
+                println!("  {}", message);
+                continue;
+            }
+        };
+        for test in &prog.tests {
+            let mut memory = Memory::new();
+            memory.programs = programs.clone();
+            let memory = memory.child(&prog.name);
+
+            let name = test.var_name.as_ref().unwrap();
+            match interpreter::run_test(&prog, test, memory) {
+                Ok(()) => {
+                    passed += 1;
+                    println!("ok   {}: {}", path.display(), name);
+                }
+                Err(message) => {
+                    failed += 1;
+                    println!("FAIL {}: {}", path.display(), name);
+                    for line in message.lines() {
+                        println!("  {}", line);
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+    failed == 0
+}
+
+fn find_sources(dir: &PathBuf, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // fixtures are files meant to fail, tested only when their directory is given itself
+        if path.is_dir() && !path.ends_with("fixtures") {
+            find_sources(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "sq") {
+            files.push(path);
+        }
+    }
+}
@@ -1,16 +1,23 @@
-use std::cell::RefCell;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
 
+use super::builtins::{call_builtin, keys};
 use super::parser::expr::{Expression, ExpressionType};
-use super::parser::statement::{Program, Statement, StatementType};
-use super::runtime_types::{History, HistoryCollection, Memory, VariableType};
+use super::parser::statement::{Parameter, ParameterMode, Program, Statement, StatementType};
+use super::runtime_types::{
+    Argument, Dependent, Generator, History, HistoryCollection, Memory, Passing, RuntimeError, Temporal,
+    VariableType, Violation,
+};
 use crate::interpreter::runtime_types::SharedHistory;
 use crate::user_options::USER_OPTIONS;
 
 macro_rules! perform_arth_op {
     ($x:ident, $y:ident, $memory:ident, $op:tt) => {
         match (&calculate_expression($x.unwrap(), $memory).bool_to_number(), &calculate_expression($y.unwrap(), $memory).bool_to_number()) {
-            (VariableType::INTEGER(x), VariableType::INTEGER(y)) => VariableType::INTEGER((*x $op *y) as i64),
+            (VariableType::INTEGER(x), VariableType::INTEGER(y)) => VariableType::INTEGER(integer_op(*x, *y, stringify!($op))),
             (VariableType::FLOAT(x), VariableType::FLOAT(y)) => VariableType::FLOAT((*x $op *y)  as f64),
             (VariableType::FLOAT(x), VariableType::INTEGER(y)) => VariableType::FLOAT(*x $op (*y as f64) as f64),
             (VariableType::INTEGER(x), VariableType::FLOAT(y)) => VariableType::FLOAT(((*x as f64) $op *y) as f64),
@@ -47,6 +54,22 @@ macro_rules! perform_log_op {
     }
 }
 
+// integer arithmetic, raising a runtime error where the operation would panic
+fn integer_op(x: i64, y: i64, op: &str) -> i64 {
+    let result = match op {
+        "+" => x.checked_add(y),
+        "-" => x.checked_sub(y),
+        "*" => x.checked_mul(y),
+        "/" => x.checked_div(y),
+        _ => x.checked_rem(y),
+    };
+    match result {
+        Some(result) => result,
+        None if y == 0 => runtime_error!("division by zero: {} {} {}", x, op, y),
+        None => runtime_error!("integer overflow: {} {} {}", x, op, y),
+    }
+}
+
 pub fn calculate_expression(expr: Box<Expression>, memory: &mut Memory) -> VariableType {
     let lhs = expr.lhs;
     let rhs = expr.rhs;
@@ -68,6 +91,14 @@ pub fn calculate_expression(expr: Box<Expression>, memory: &mut Memory) -> Varia
         ExpressionType::NOT => calculate_expression(lhs.unwrap(), memory).negate(),
 
         ExpressionType::ABS => calculate_expression(lhs.unwrap(), memory).abs(),
+        ExpressionType::UMIN => match calculate_expression(lhs.unwrap(), memory).bool_to_number() {
+            VariableType::INTEGER(x) => match x.checked_neg() {
+                Some(x) => VariableType::INTEGER(x),
+                None => runtime_error!("integer overflow: -({})", x),
+            },
+            VariableType::FLOAT(x) => VariableType::FLOAT(-x),
+            x => runtime_error!("cannot negate {}", x),
+        },
 
         ExpressionType::FACTORIAL => {
             let x = calculate_expression(lhs.unwrap(), memory).convert_int();
@@ -107,35 +138,185 @@ pub fn calculate_expression(expr: Box<Expression>, memory: &mut Memory) -> Varia
         ExpressionType::INTEGER(x) => VariableType::INTEGER(x),
         ExpressionType::FLOAT(x) => VariableType::FLOAT(x),
         ExpressionType::BOOL(x) => VariableType::BOOL(x),
+        ExpressionType::STRING(x) => VariableType::STRING(x),
 
-        ExpressionType::IDENTIFIER(s) => {
-            let history: SharedHistory = memory.get_history(s);
-            let borrow = history.borrow();
-            borrow.get_past(borrow.len() - 1).clone()
+        ExpressionType::MAP => {
+            let mut map = BTreeMap::new();
+            for entry in expr.args.chunks(2) {
+                let key = calculate_expression(entry[0].clone(), memory).as_key();
+                let value = calculate_expression(entry[1].clone(), memory);
+                map.insert(key, value);
+            }
+            VariableType::MAP(Rc::new(map))
+        }
+        ExpressionType::INDEX => {
+            let container = calculate_expression(lhs.unwrap(), memory);
+            let key = calculate_expression(rhs.unwrap(), memory).as_key();
+            match &container {
+                VariableType::MAP(map) => match map.get(&key) {
+                    Some(x) => x.clone(),
+                    None => runtime_error!("key {} not found in {}", key, container),
+                },
+                _ => runtime_error!("cannot index into {}, it is not a map", container),
+            }
+        }
+        ExpressionType::HAS => {
+            let container = calculate_expression(lhs.unwrap(), memory);
+            let key = calculate_expression(rhs.unwrap(), memory).as_key();
+            match &container {
+                VariableType::MAP(map) => VariableType::BOOL(map.contains_key(&key)),
+                _ => runtime_error!("cannot check for key in {}, it is not a map", container),
+            }
+        }
+        ExpressionType::CALL(name) => call_builtin(&name, &expr.args, memory),
+        // like identifiers, history values evaluate to their most recent value
+        ExpressionType::HISTORY => calculate_expression(expr.args.last().unwrap().clone(), memory),
+        ExpressionType::WINDOW(_) => last_value(&expr.var_name.unwrap(), memory),
+        ExpressionType::FORK => {
+            let fork = fork_history(expr.var_name.unwrap(), lhs.unwrap(), memory);
+            fork.get_past(fork.len() - 1)
         }
+        ExpressionType::SLICE(from_end) => {
+            let name = expr.var_name.unwrap();
+            let slice = slice_history(name, lhs.unwrap(), rhs.unwrap(), from_end, memory);
+            slice.get_past(slice.len() - 1)
+        }
+        ExpressionType::RUN(call) => match run_sub_program(&call, memory) {
+            Some(x) => x,
+            None => runtime_error!("{} did not return a value", program_name(&call)),
+        },
+
+        ExpressionType::NEXT(name) => {
+            // the step may append to histories shared with the generator, which a try or
+            // atomic block around it has to be able to take back
+            let shared = match memory.generators.get(&name) {
+                Some(generator) => generator.shared.clone(),
+                None => vec![],
+            };
+            shared.iter().for_each(|history| memory.save_history(history));
+            let generator = match memory.generators.get_mut(&name) {
+                Some(x) => x,
+                None => runtime_error!("{} is not a started program", name),
+            };
+            if generator.done {
+                runtime_error!("{} is done, it has no more values", name);
+            }
+            generator.done = step_program(&generator.program, &mut generator.memory);
+            let value = generator.memory.result.take();
+            match (value, &generator.last) {
+                (Some(x), _) => {
+                    generator.last = Some(x.clone());
+                    x
+                }
+                // the caller can't know the iteration will end it, so it gets the last value again
+                (None, Some(last)) if generator.done => last.clone(),
+                (None, None) if generator.done => VariableType::BOOL(false),
+                (None, _) => runtime_error!("{} did not yield a value", generator.program.name),
+            }
+        }
+        ExpressionType::DONE(name) => match memory.generators.get(&name) {
+            Some(generator) => VariableType::BOOL(generator.done),
+            None => runtime_error!("{} is not a started program", name),
+        },
+
+        ExpressionType::META(name) => match name.as_str() {
+            "iter" => VariableType::INTEGER(memory.iteration as i64),
+            // the top level program is at depth 0
+            "depth" => VariableType::INTEGER(memory.call_chain.len().saturating_sub(1) as i64),
+            "program" => match memory.call_chain.last() {
+                Some(name) => VariableType::STRING(name.to_string()),
+                None => VariableType::STRING(String::new()),
+            },
+            _ => unreachable!(),
+        },
+        ExpressionType::AT => {
+            let iteration = match calculate_expression(lhs.unwrap(), memory).convert_int() {
+                VariableType::INTEGER(x) => x,
+                x => runtime_error!("at expects an iteration, got {}", x),
+            };
+            let mut snapshot = snapshot(iteration, memory);
+            calculate_expression(rhs.unwrap(), &mut snapshot)
+        }
+        ExpressionType::EVER(id)
+        | ExpressionType::ALWAYS(id)
+        | ExpressionType::SINCE(id)
+        | ExpressionType::FOR(id) => temporal_condition(expr.exp_type, id, lhs.unwrap(), rhs, memory),
+
+        ExpressionType::IDENTIFIER(s) => last_value(&s, memory),
         ExpressionType::PREV(s) => {
-            let var_history: SharedHistory = memory.get_history(s);
-            let borrow = var_history.borrow();
-            if borrow.len() == 1 {
-                return borrow.get_past(0).clone();
+            let count = match lhs {
+                Some(count) => match calculate_expression(count, memory).convert_int() {
+                    VariableType::INTEGER(x) if x >= 0 => x as usize,
+                    x => runtime_error!("prev expects a number of steps back, got {}", x),
+                },
+                None => 1,
+            };
+            let len = memory.get_history(s.clone()).borrow().len();
+            if count < len {
+                return memory.get_history(s).borrow().get_past(len - 1 - count);
+            }
+            match rhs {
+                Some(default) => calculate_expression(default, memory),
+                None => runtime_error!("prev {} {}: {} needs more than {} values, it has {}", count, s, s, count, len),
+            }
+        }
+        ExpressionType::FIRST(s) => {
+            let history = memory.get_history(s.clone());
+            let borrow = history.borrow();
+            if borrow.len() == 0 {
+                runtime_error!("first {}: {} has no values", s, s);
+            }
+            borrow.get_past(0)
+        }
+        ExpressionType::DELTA(s) => {
+            let history: SharedHistory = memory.get_history(s.clone());
+            let len = history.borrow().len();
+            if len < 2 {
+                return match rhs {
+                    Some(default) => calculate_expression(default, memory),
+                    None => runtime_error!("delta {}: {} needs at least 2 values, it has {}", s, s, len),
+                };
+            }
+            let borrow = history.borrow();
+            difference(borrow.get_past(len - 1), borrow.get_past(len - 2))
+        }
+        ExpressionType::CHANGED(s) => {
+            // a value is new if it was appended after the previous iteration ended
+            let since = memory.iteration.checked_sub(1).map_or(0, |i| memory.iteration_ends[i]);
+            match memory.cells.get(&s) {
+                // an empty history, like keys of an empty map, has no new value either
+                Some(history) if history.borrow().len() > 0 => {
+                    let borrow = history.borrow();
+                    VariableType::BOOL(borrow.get_stamp(borrow.len() - 1).time > since)
+                }
+                _ => VariableType::BOOL(false),
             }
-            borrow.get_past(borrow.len() - 2).clone()
         }
         ExpressionType::ACCESSOR => {
             let name = expr.var_name.unwrap();
-            let var_history: History = memory.get_history(name).borrow().clone();
 
             if !matches!(lhs, None) {
                 // could clean this up with a simpler way to get values out of VariableType
                 if let VariableType::INTEGER(x) =
                     calculate_expression(lhs.unwrap(), memory).convert_int()
                 {
+                    if x < 0 {
+                        runtime_error!("{}::${}: an index can't be negative", x, name);
+                    }
+                    let var_history: History = with_terms(&name, x as usize + 1, memory);
+                    if x as usize >= var_history.len() {
+                        runtime_error!("{}::${} is out of bounds, {} has {} values", x, name, name, var_history.len());
+                    }
                     return var_history.get_past(x as usize).clone();
                 }
             } else if !matches!(rhs, None) {
                 if let VariableType::INTEGER(x) =
                     calculate_expression(rhs.unwrap(), memory).convert_int()
                 {
+                    if x < 0 {
+                        runtime_error!("${}::{}: an index can't be negative", name, x);
+                    }
+                    let var_history: History = memory.get_history(name).borrow().clone();
                     return var_history
                         .get_past(var_history.len() - 1 - (x as usize))
                         .clone();
@@ -152,17 +333,404 @@ pub fn calculate_expression(expr: Box<Expression>, memory: &mut Memory) -> Varia
     }
 }
 
+// memory holding the histories as they were at the end of the given iteration. histories
+// that didn't have a value yet are left out
+fn snapshot(iteration: i64, memory: &Memory) -> Memory {
+    if iteration < 0 || iteration as usize > memory.iteration {
+        runtime_error!("cannot look at iteration {}, the program is at iteration {}", iteration, memory.iteration);
+    }
+    // the current iteration isn't over yet, so it is seen as it is now
+    let time = match memory.iteration_ends.get(iteration as usize) {
+        Some(time) => *time,
+        None => memory.clock.get(),
+    };
+
+    let mut snapshot = Memory::new();
+    snapshot.programs = memory.programs.clone();
+    snapshot.call_chain = memory.call_chain.clone();
+    snapshot.clock = memory.clock.clone();
+    snapshot.iteration = iteration as usize;
+    snapshot.iteration_ends = memory.iteration_ends.clone(); // the ends after its iteration are never looked at
+    for (name, history) in &memory.cells {
+        let past = history.borrow().as_of(time);
+        if past.len() > 0 {
+            snapshot.insert_history(name.clone(), Rc::new(RefCell::new(past)));
+        }
+    }
+    snapshot
+}
+
+// a temporal condition holds or not in each iteration with the histories as they were at its
+// end. the iterations that finished since it was last checked are folded into the state kept
+// under its id, so it doesn't matter whether it was checked in them, and the current iteration
+// is looked at as it is now
+fn temporal_condition(
+    exp_type: ExpressionType,
+    id: usize,
+    lhs: Box<Expression>,
+    rhs: Option<Box<Expression>>,
+    memory: &mut Memory,
+) -> VariableType {
+    let mut state = match memory.temporal.remove(&id) {
+        Some(state) => state,
+        None => Temporal { held: matches!(exp_type, ExpressionType::ALWAYS(_)), ..Default::default() },
+    };
+    // iterations from before one of its histories had a value don't count
+    let names: Vec<String> = [Some(&lhs), rhs.as_ref()]
+        .into_iter()
+        .flatten()
+        .flat_map(|e| e.histories())
+        .filter(|name| memory.cells.contains_key(name))
+        .collect();
+    while state.checked < memory.iteration {
+        let mut past = snapshot(state.checked as i64, memory);
+        if names.iter().all(|name| past.cells.contains_key(name)) {
+            let cond = calculate_expression(lhs.clone(), &mut past).as_bool();
+            state = match exp_type {
+                ExpressionType::SINCE(_) => {
+                    let start = calculate_expression(rhs.clone().unwrap(), &mut past).as_bool();
+                    state.next(&exp_type, cond, start)
+                }
+                _ => state.next(&exp_type, cond, false),
+            };
+        }
+        state.checked += 1;
+    }
+
+    let cond = calculate_expression(lhs, memory).as_bool();
+    let result = match exp_type {
+        ExpressionType::SINCE(_) => {
+            let start = calculate_expression(rhs.unwrap(), memory).as_bool();
+            state.next(&exp_type, cond, start).held
+        }
+        ExpressionType::FOR(_) => {
+            let count = match calculate_expression(rhs.unwrap(), memory).convert_int() {
+                VariableType::INTEGER(x) => x,
+                x => runtime_error!("for expects a number of iterations, got {}", x),
+            };
+            state.next(&exp_type, cond, false).streak as i64 >= count
+        }
+        _ => state.next(&exp_type, cond, false).held,
+    };
+    memory.temporal.insert(id, state);
+    VariableType::BOOL(result)
+}
+
+// a history, with the terms it doesn't have yet up to len worked out on a copy if it is defined
+// by a recurrence. the history itself only gets them as the iterations go by, so reading ahead
+// doesn't change it. terms that depend on anything but the history itself aren't known until then
+fn with_terms(name: &str, len: usize, memory: &mut Memory) -> History {
+    let history = memory.get_history(name.to_string()).borrow().clone();
+    let recurrence = match memory.recurrences.iter().find(|(n, _)| n == name) {
+        Some((_, recurrence)) if history.len() < len => recurrence.clone(),
+        _ => return history,
+    };
+    if !recurrence.histories().iter().all(|n| n == name) || !self_contained(&recurrence) {
+        runtime_error!(
+            "{}::${} is past the end of {}, which has {} values. its later terms depend on more than {}, so they aren't known yet",
+            len - 1, name, name, history.len(), name
+        );
+    }
+
+    // the copy is extended in a memory of its own, where it isn't a recurrence any more
+    let mut scratch = Memory::new();
+    scratch.programs = memory.programs.clone();
+    scratch.insert_history(name.to_string(), Rc::new(RefCell::new(history)));
+    while scratch.get_history(name.to_string()).borrow().len() < len {
+        let value = calculate_expression(recurrence.clone(), &mut scratch);
+        scratch.update_history(name.to_string(), value);
+    }
+    let history = scratch.get_history(name.to_string()).borrow().clone();
+    history
+}
+
+// whether an expression gives the same value whenever it is evaluated on the same histories
+fn self_contained(expr: &Expression) -> bool {
+    let depends_on_when = matches!(
+        expr.exp_type,
+        ExpressionType::META(_) | ExpressionType::RUN(_) | ExpressionType::NEXT(_) | ExpressionType::DONE(_) | ExpressionType::AT
+    );
+    !depends_on_when && expr.lhs.iter().chain(expr.rhs.iter()).chain(expr.args.iter()).all(|e| self_contained(e))
+}
+
+// adds the next term of every history defined by a recurrence, in the order they were written
+fn advance_recurrences(memory: &mut Memory) {
+    for (name, recurrence) in memory.recurrences.clone() {
+        let value = calculate_expression(recurrence, memory);
+        memory.update_history(name, value);
+    }
+}
+
+fn check_writable(name: &str, program: &Program, memory: &Memory) {
+    if memory.is_recurrence(name) {
+        runtime_error!("cannot write to {}, it is defined by a recurrence", name);
+    } else if program.derived.iter().any(|d| d.var_name.as_deref() == Some(name)) {
+        runtime_error!("cannot write to {}, it is derived from other histories", name);
+    }
+}
+
+// runs what watches the histories that got a value since the last call: derived histories are
+// recomputed first and on change blocks run after, each in the order they were written
+fn react(program: &Program, memory: &mut Memory) {
+    loop {
+        let changed = std::mem::take(&mut memory.changed);
+        if changed.is_empty() {
+            return;
+        }
+        for name in changed {
+            if memory.reacting.contains(&name) {
+                let chain = [memory.reacting.clone(), vec![name.clone()]].concat();
+                runtime_error!("{} changed again while reacting to its change: {}", name, chain.join(" -> "));
+            }
+            memory.reacting.push(name.clone());
+            let dependents = memory.dependents[&name].clone();
+            for dependent in dependents {
+                let derived = match dependent {
+                    Dependent::Trigger(i) => {
+                        run_statements(program, program.triggers[i].code_block.as_ref().unwrap(), memory);
+                        continue;
+                    }
+                    Dependent::Derived(i) => &program.derived[i],
+                };
+                // a derived history gets its first value once all of its sources have one
+                if derived.arg_names.iter().flatten().all(|s| memory.cells.contains_key(s)) {
+                    let value = calculate_expression(derived.expr.clone().unwrap(), memory);
+                    memory.update_history(derived.var_name.clone().unwrap(), value);
+                    react(program, memory);
+                }
+            }
+            memory.reacting.pop();
+        }
+    }
+}
+
+pub fn window_size(size: Box<Expression>, memory: &mut Memory) -> usize {
+    match calculate_expression(size, memory).convert_int() {
+        VariableType::INTEGER(x) if x > 0 => x as usize,
+        x => runtime_error!("a window needs a size of at least 1, got {}", x),
+    }
+}
+
+fn difference(mut x: VariableType, mut y: VariableType) -> VariableType {
+    match (x.bool_to_number(), y.bool_to_number()) {
+        (VariableType::INTEGER(x), VariableType::INTEGER(y)) => VariableType::INTEGER(x - y),
+        (VariableType::FLOAT(x), VariableType::FLOAT(y)) => VariableType::FLOAT(x - y),
+        (VariableType::FLOAT(x), VariableType::INTEGER(y)) => VariableType::FLOAT(x - y as f64),
+        (VariableType::INTEGER(x), VariableType::FLOAT(y)) => VariableType::FLOAT(x as f64 - y),
+        (x, y) => runtime_error!("cannot subtract {} from {}", y, x),
+    }
+}
+
 fn print_variable(x: &VariableType) {
     match x {
         VariableType::BOOL(x) => print!("{} ", x),
         VariableType::INTEGER(x) => print!("{} ", x),
         VariableType::FLOAT(x) => print!("{} ", x),
         VariableType::STRING(x) => print!("{} ", x),
+        VariableType::MAP(_) => print!("{} ", x),
+    }
+}
+
+// the most recent value of a history, which is what a name evaluates to
+fn last_value(name: &str, memory: &Memory) -> VariableType {
+    let history = memory.get_history(name.to_string());
+    let borrow = history.borrow();
+    if borrow.len() == 0 {
+        runtime_error!("{} has no values", name);
+    }
+    borrow.get_past(borrow.len() - 1)
+}
+
+// the values of $a::i..j (counting back from the most recent value) or i..j::$a
+fn slice_history(
+    name: String,
+    start: Box<Expression>,
+    end: Box<Expression>,
+    from_end: bool,
+    memory: &mut Memory,
+) -> History {
+    let start = calculate_expression(start, memory).convert_int();
+    let end = calculate_expression(end, memory).convert_int();
+
+    let (start, end) = match (start, end) {
+        (VariableType::INTEGER(x), VariableType::INTEGER(y)) => (x, y),
+        _ => unreachable!(),
+    };
+    let history: History = match from_end {
+        false if end > 0 => with_terms(&name, end as usize, memory),
+        _ => memory.get_history(name.clone()).borrow().clone(),
+    };
+    let len = history.len() as i64;
+    if start < 0 || end > len || start >= end {
+        runtime_error!("range {}..{} is out of bounds for {} with {} values", start, end, name, len);
+    }
+
+    if from_end {
+        return history.slice((len - end) as usize, (len - start) as usize);
+    }
+    history.slice(start as usize, end as usize)
+}
+
+// fork a at i: a new history sharing the values of a up to and including index i
+fn fork_history(name: String, index: Box<Expression>, memory: &mut Memory) -> History {
+    let index = calculate_expression(index, memory).convert_int();
+    let history = memory.get_history(name.clone());
+    let len = history.borrow().len();
+    match index {
+        VariableType::INTEGER(i) if i >= 0 && (i as usize) < len => history.borrow_mut().fork(i as usize),
+        x => runtime_error!("fork {} at {}: {} has {} values", name, x, name, len),
     }
 }
 
+// evaluates a run argument. names are handed over as they are and copied later if the
+// parameter is val or const, anything else (including module constants and const parameters,
+// which are read-only) is evaluated into a new history
+fn argument(expr: &Expression, name: Option<String>, memory: &mut Memory) -> Argument {
+    let (history, passing) = match &expr.exp_type {
+        ExpressionType::REFERENCE(s) => (memory.get_history(s.clone()), Passing::REFERENCE),
+        ExpressionType::IDENTIFIER(s) if !s.contains('.') && !memory.constants.contains(s) => {
+            (memory.get_history(s.clone()), Passing::NAME)
+        }
+        ExpressionType::IDENTIFIER(s) => {
+            let copy = memory.get_history(s.clone()).borrow().clone();
+            (Rc::new(RefCell::new(copy)), Passing::VALUE)
+        }
+        ExpressionType::HISTORY => {
+            let mut history = History::new();
+            for value in &expr.args {
+                history.add(calculate_expression(value.clone(), memory));
+            }
+            (Rc::new(RefCell::new(history)), Passing::VALUE)
+        }
+        ExpressionType::WINDOW(_) => {
+            let s = expr.var_name.clone().unwrap();
+            let size = window_size(expr.lhs.clone().unwrap(), memory);
+            let history = memory.get_history(s).borrow().clone();
+            let slice = history.slice(history.len().saturating_sub(size), history.len());
+            (Rc::new(RefCell::new(slice)), Passing::VALUE)
+        }
+        ExpressionType::FORK => {
+            let fork = fork_history(expr.var_name.clone().unwrap(), expr.lhs.clone().unwrap(), memory);
+            (Rc::new(RefCell::new(fork)), Passing::VALUE)
+        }
+        ExpressionType::CALL(f) if f == "keys" => {
+            (Rc::new(RefCell::new(keys(&expr.args, memory))), Passing::VALUE)
+        }
+        ExpressionType::SLICE(from_end) => {
+            let s = expr.var_name.clone().unwrap();
+            let (start, end) = (expr.lhs.clone().unwrap(), expr.rhs.clone().unwrap());
+            let slice = slice_history(s, start, end, *from_end, memory);
+            (Rc::new(RefCell::new(slice)), Passing::VALUE)
+        }
+        _ => {
+            let value = calculate_expression(Box::new(expr.clone()), memory);
+            (History::alloc(String::new(), value), Passing::VALUE)
+        }
+    };
+    Argument { name, history, passing }
+}
+
+fn program_name(statement: &Statement) -> String {
+    match (&statement.sub_program, &statement.program_path) {
+        (Some(program), _) => program.name.clone(),
+        (None, path) => path.as_ref().unwrap().to_string_lossy().to_string(),
+    }
+}
+
+// a program that runs itself is only parsed once, so the statements that run it
+// look it up in the cache instead of holding it
+fn cached_program(statement: &Statement, memory: &Memory) -> Rc<Program> {
+    let path = statement.program_path.as_ref().unwrap();
+    match memory.programs.borrow().get(path) {
+        Some(program) => program.clone(),
+        None => runtime_error!("{} has not been loaded", path.to_string_lossy()),
+    }
+}
+
+// the program a run or start statement calls
+fn called_program(statement: &Statement, memory: &Memory) -> Rc<Program> {
+    match &statement.sub_program {
+        Some(program) => program.clone(),
+        None => cached_program(statement, memory),
+    }
+}
+
+// memory for a program run from the one using memory, as long as the run isn't nested too deep
+fn sub_memory(sub_prog: &Program, memory: &Memory) -> Memory {
+    let max_depth = USER_OPTIONS.lock().unwrap().max_depth;
+    let sub_memory = memory.child(&sub_prog.name);
+    if sub_memory.call_chain.len() > max_depth {
+        let chain = &sub_memory.call_chain;
+        // the chain can be long, so only its start and end are shown
+        let shown: Vec<Rc<str>> = if chain.len() > 8 {
+            [&chain[..3], &[Rc::from("...")], &chain[chain.len() - 4..]].concat()
+        } else {
+            chain.clone()
+        };
+        runtime_error!(
+            "programs ran each other more than {} deep: {}",
+            max_depth,
+            shown.join(" -> ")
+        );
+    }
+    sub_memory
+}
+
+// the arguments given to a run or start statement
+fn call_arguments(
+    statement: &Statement,
+    sub_prog: &Program,
+    memory: &mut Memory,
+) -> Option<HistoryCollection> {
+    if sub_prog.parameters.is_none() && statement.alt_exps.is_empty() {
+        return None;
+    }
+
+    let mut parameters: HistoryCollection = HistoryCollection::new();
+    for (arg, name) in statement.alt_exps.iter().zip(&statement.arg_names) {
+        let argument = argument(arg, name.clone(), memory);
+        if argument.passing != Passing::VALUE {
+            memory.save_history(&argument.history); // the program may append to it
+        }
+        parameters.push(argument);
+    }
+    Some(parameters)
+}
+
+// runs the program called by a run statement, returning the value it returned if any
+fn run_sub_program(statement: &Statement, memory: &mut Memory) -> Option<VariableType> {
+    let sub_prog: Rc<Program> = called_program(statement, memory);
+    let parameters = call_arguments(statement, &sub_prog, memory);
+
+    if !statement.persistent {
+        return execute_program(&sub_prog, Some(sub_memory(&sub_prog, memory)), parameters);
+    }
+
+    // a persistent program starts once and then picks up its memory where the last run left it.
+    // the memory is taken out while the program runs so a run inside it can't reach it
+    let (mut sub_memory, started) = match memory.persistent.remove(&statement.id) {
+        Some(x) => (x, true),
+        None => (sub_memory(&sub_prog, memory), false),
+    };
+    if started {
+        bind_parameters(&sub_prog, parameters.unwrap_or_default(), &mut sub_memory);
+    } else {
+        start_program(&sub_prog, &mut sub_memory, parameters);
+    }
+    // it is put back even when the run raises an error, so a try or atomic block around the
+    // run statement doesn't lose it
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| run_until_expect(&sub_prog, &mut sub_memory)));
+    memory.persistent.insert(statement.id, sub_memory);
+    outcome.unwrap_or_else(|error| panic::resume_unwind(error))
+}
+
 fn run_statements(program: &Program, statements: &Vec<Statement>, memory: &mut Memory) {
     for statement in statements {
+        react(program, memory); // to what the statement before appended
+        if memory.result.is_some() {
+            break; // returned
+        }
+
         if USER_OPTIONS.lock().unwrap().debug {
             println!("{:?}", statement.statement_type.clone());
         }
@@ -170,19 +738,59 @@ fn run_statements(program: &Program, statements: &Vec<Statement>, memory: &mut M
         match statement.statement_type.clone() {
             // TODO: split statement execution into different function
             StatementType::ASSIGN => {
-                let val = calculate_expression(statement.expr.clone().unwrap(), memory);
+                let expr = statement.expr.clone().unwrap();
                 let name: String = statement.var_name.clone().unwrap();
+                check_writable(&name, program, memory);
+
+                // b <- fork a at i and b <- keys(m) replace b, like a copy, rather than appending to it
+                if expr.exp_type == ExpressionType::FORK {
+                    let fork = fork_history(expr.var_name.unwrap(), expr.lhs.unwrap(), memory);
+                    memory.replace(name, fork);
+                    continue;
+                }
+                if matches!(&expr.exp_type, ExpressionType::CALL(f) if f == "keys") {
+                    let keys = keys(&expr.args, memory);
+                    memory.replace(name, keys);
+                    continue;
+                }
+                let val = calculate_expression(expr, memory);
 
                 memory.update_history(name, val);
             }
 
+            StatementType::INSERT => {
+                // a keyed set appends a new map to the history rather than changing the old one
+                let name: String = statement.var_name.clone().unwrap();
+                check_writable(&name, program, memory);
+                let key = calculate_expression(statement.alt_exps[0].clone(), memory).as_key();
+                let val = calculate_expression(statement.expr.clone().unwrap(), memory);
+
+                let map = match memory.cells.get(&name) {
+                    Some(history) if history.borrow().len() > 0 => {
+                        let borrow = history.borrow();
+                        borrow.get_past(borrow.len() - 1)
+                    }
+                    _ => VariableType::MAP(Rc::new(BTreeMap::new())),
+                };
+                memory.update_history(name, map.with_key(key, val));
+            }
+
             StatementType::COPY => {
                 let destination = statement.var_name.as_ref().unwrap().to_string();
                 let source = statement.alt_var_name.as_ref().unwrap().to_string();
+                check_writable(&destination, program, memory);
 
                 memory.copy(source, destination);
             }
 
+            StatementType::ALIAS => {
+                let destination = statement.var_name.as_ref().unwrap().to_string();
+                let source = statement.alt_var_name.as_ref().unwrap().to_string();
+                check_writable(&destination, program, memory);
+
+                memory.alias(source, destination);
+            }
+
             StatementType::PRINT => {
                 let exp = statement.expr.as_ref().unwrap();
                 if matches!(exp.exp_type, ExpressionType::NONE) {
@@ -200,7 +808,7 @@ fn run_statements(program: &Program, statements: &Vec<Statement>, memory: &mut M
             }
 
             StatementType::TPRINT => {
-                if !program.top_level {
+                if !memory.top_level {
                     continue;
                 }
                 let exp = statement.expr.as_ref().unwrap(); // TODO: copied from print for now, will clean up executor later
@@ -218,6 +826,20 @@ fn run_statements(program: &Program, statements: &Vec<Statement>, memory: &mut M
                 println!("");
             }
 
+            StatementType::ATOMIC => run_atomic(program, statement, memory),
+
+            StatementType::ASSERT => {
+                let expr = statement.expr.clone().unwrap();
+                if !calculate_expression(expr.clone(), memory).as_bool() {
+                    let message = match &statement.alt_var_name {
+                        Some(message) => format!(": {}", message),
+                        None => String::new(),
+                    };
+                    let report = format!("assert {} failed{}{}", expr, message, history_report(&expr, memory));
+                    panic::panic_any(Violation(report));
+                }
+            }
+
             StatementType::IF => {
                 if calculate_expression(statement.expr.clone().unwrap(), memory).as_bool() {
                     run_statements(program, &statement.code_block.as_ref().unwrap(), memory);
@@ -244,28 +866,32 @@ fn run_statements(program: &Program, statements: &Vec<Statement>, memory: &mut M
             }
 
             StatementType::RUN => {
-                // for each variable insert a shared insert history with that name in current memory
-
-                let sub_prog: Box<Program> = statement.sub_program.as_ref().unwrap().clone();
-                let mut parameters: Option<HistoryCollection> = None;
+                run_sub_program(statement, memory);
+            }
 
-                if let Some(parameter_names) = sub_prog.parameters {
-                    let given_histories = statement.var_list.as_ref().unwrap();
-                    let mut new_parameters: HistoryCollection = HistoryCollection::new();
-                    assert_eq!(given_histories.len(), parameter_names.len());
+            StatementType::START => {
+                // starting a generator again throws away the old one
+                let program = called_program(statement, memory);
+                let parameters = call_arguments(statement, &program, memory);
+                let shared = parameters
+                    .iter()
+                    .flatten()
+                    .filter(|argument| argument.passing != Passing::VALUE)
+                    .map(|argument| argument.history.clone())
+                    .collect();
+                let mut generator_memory = sub_memory(&program, memory);
+                start_program(&program, &mut generator_memory, parameters);
 
-                    for (current_name, _expected_name) in
-                        given_histories.iter().zip(parameter_names.iter())
-                    {
-                        let hist: Rc<RefCell<History>> = memory.get_history(current_name.clone());
-                        new_parameters.push(hist.clone());
-                    }
+                let generator = Generator { program, memory: generator_memory, done: false, last: None, shared };
+                memory.generators.insert(statement.var_name.clone().unwrap(), generator);
+            }
 
-                    parameters = Some(new_parameters);
-                }
+            StatementType::RETURN => {
+                memory.result = Some(calculate_expression(statement.expr.clone().unwrap(), memory));
+            }
 
-                execute_program(statement.sub_program.as_ref().unwrap(), None, parameters);
-                // TODO: replace shared memory with parameters
+            StatementType::YIELD => {
+                memory.yielded = Some(calculate_expression(statement.expr.clone().unwrap(), memory));
             }
 
             _ => {
@@ -273,40 +899,112 @@ fn run_statements(program: &Program, statements: &Vec<Statement>, memory: &mut M
             }
         }
     }
+    react(program, memory);
+}
+
+// a module's constants are the histories set by its begin block, exposed as namespace.name.
+// modules that take parameters only expose the constants of their own modules, since their
+// begin block can't run without them
+fn load_modules(program: &Program, memory: &mut Memory) {
+    for (namespace, module) in &program.modules {
+        let mut module_memory = memory.child(&module.name);
+        load_modules(module, &mut module_memory);
+
+        if let (Some(begin), None) = (&module.begin, &module.parameters) {
+            run_statements(module, begin.code_block.as_ref().unwrap(), &mut module_memory);
+        }
+
+        for (name, history) in module_memory.cells {
+            memory.insert_history(format!("{}.{}", namespace, name), history);
+        }
+    }
+}
+
+// the program shares the caller's history unless the parameter is val or const, which get a copy
+fn pass_argument(program: &Program, param: &Parameter, arg: Argument) -> SharedHistory {
+    match (param.mode, arg.passing) {
+        (ParameterMode::REF, Passing::VALUE) => runtime_error!(
+            "{}: parameter {} is ref, it has to be given a history name",
+            program.name, param.name
+        ),
+        (ParameterMode::VAL | ParameterMode::CONST, Passing::REFERENCE) => runtime_error!(
+            "{}: parameter {} is passed by value, it can't be given a reference",
+            program.name, param.name
+        ),
+        (ParameterMode::VAL | ParameterMode::CONST, Passing::NAME) => {
+            Rc::new(RefCell::new(arg.history.borrow().clone()))
+        }
+        _ => arg.history,
+    }
+}
+
+// positional arguments fill parameters in order, named ones by name, and whatever is left
+// falls back to its default, evaluated after every given argument is in memory
+fn bind_parameters(program: &Program, arguments: HistoryCollection, memory: &mut Memory) {
+    let expected = program.parameters.clone().unwrap_or_default();
+    let mut given: Vec<bool> = vec![false; expected.len()];
+    let mut position = 0;
+
+    for arg in arguments {
+        let index = match &arg.name {
+            Some(name) => match expected.iter().position(|p| p.name == *name) {
+                Some(i) => i,
+                None => runtime_error!("{}: no parameter named {}", program.name, name),
+            },
+            None => {
+                position += 1;
+                position - 1
+            }
+        };
+        if index >= expected.len() {
+            runtime_error!("{}: got too many parameters, expected {}", program.name, expected.len());
+        }
+        if given[index] {
+            runtime_error!("{}: parameter {} given twice", program.name, expected[index].name);
+        }
+        given[index] = true;
+        let history = pass_argument(program, &expected[index], arg);
+        memory.insert_history(expected[index].name.clone(), history);
+    }
+    memory.constants = expected
+        .iter()
+        .filter(|param| param.mode == ParameterMode::CONST)
+        .map(|param| param.name.clone())
+        .collect();
+
+    for (param, given) in expected.iter().zip(given) {
+        if given {
+            continue;
+        }
+        match &param.default {
+            Some(default) => {
+                let arg = argument(default, None, memory);
+                let history = pass_argument(program, param, arg);
+                memory.insert_history(param.name.clone(), history);
+            }
+            None => runtime_error!("{}: missing parameter {}", program.name, param.name),
+        }
+    }
 }
 
 pub fn execute_program(
     program: &Program,
     shared_memory: Option<Memory>,
     parameters: Option<HistoryCollection>,
-) {
+) -> Option<VariableType> {
     let mut memory = match shared_memory {
         Some(x) => x,
         None => Memory::new(),
     };
 
-    // todo - should clean up this function, especially move parmeter logic to another function
-    if let Some(params) = parameters {
-        let expected_names = program
-            .parameters
-            .clone()
-            .expect("Program received unexpected parameters");
-        if params.len() != expected_names.len() {
-            panic!(
-                "{}: got {} parameters, expected {}",
-                program.name,
-                params.len(),
-                expected_names.len()
-            );
-        }
+    start_program(program, &mut memory, parameters);
+    run_until_expect(program, &mut memory)
+}
 
-        for i in 0..expected_names.len() {
-            let shared_history = params[i].clone();
-            memory.insert_history(expected_names[i].clone(), shared_history);
-        }
-    } else if matches!(program.parameters, Some(_)) {
-        panic!("{}: expected parameters, but none were given", program.name)
-    }
+// sets up the program's memory and runs its begin block
+fn start_program(program: &Program, memory: &mut Memory, parameters: Option<HistoryCollection>) {
+    load_modules(program, memory);
+    bind_parameters(program, parameters.unwrap_or_default(), memory);
 
     if USER_OPTIONS.lock().unwrap().debug {
         // probably should move this up so all programs are printed once, not once per run
@@ -318,21 +1016,223 @@ pub fn execute_program(
         }
     }
 
-    if program.expect.len() == 0 {
+    if program.expect.is_empty() && program.require.is_empty() {
         println!("WARNING: Running with no expect block, program will not terminate!");
     }
+    declare(program, memory);
+
     if let Some(begin) = &program.begin {
-        run_statements(program, &begin.code_block.as_ref().unwrap(), &mut memory);
+        run_statements(program, begin.code_block.as_ref().unwrap(), memory);
+    }
+}
+
+// sets up the histories defined with := and derive, and the on change blocks
+fn declare(program: &Program, memory: &mut Memory) {
+    for (i, derived) in program.derived.iter().enumerate() {
+        for name in derived.arg_names.iter().flatten() {
+            memory.dependents.entry(name.clone()).or_default().push(Dependent::Derived(i));
+        }
     }
-    'prog_loop: loop {
-        run_statements(program, &program.body, &mut memory);
-        // expect block logic
-        for i in 0..program.expect.len() {
-            if calculate_expression(program.expect[i].expr.clone().unwrap(), &mut memory).as_bool()
-            {
-                run_statements(program, program.expect[i].code_block.as_ref().unwrap(), &mut memory);
-                break 'prog_loop;
+    for (i, trigger) in program.triggers.iter().enumerate() {
+        let name = trigger.var_name.clone().unwrap();
+        memory.dependents.entry(name).or_default().push(Dependent::Trigger(i));
+    }
+
+    // histories defined by a recurrence start out with their seed values
+    for recurrence in &program.recurrences {
+        let name = recurrence.var_name.clone().unwrap();
+        for seed in &recurrence.alt_exps {
+            let value = calculate_expression(seed.clone(), memory);
+            memory.update_history(name.clone(), value);
+        }
+        memory.recurrences.push((name, recurrence.expr.clone().unwrap()));
+    }
+    react(program, memory);
+}
+
+// runs one iteration of the body and checks the expect blocks, returning true if one fired.
+// the value returned by the expect block, or else the last one yielded, is left in memory.result
+fn step_program(program: &Program, memory: &mut Memory) -> bool {
+    memory.result = None;
+    memory.yielded = None;
+
+    for require in &program.require {
+        if !calculate_expression(require.expr.clone().unwrap(), memory).as_bool() {
+            run_finally(program, memory);
+            return true;
+        }
+    }
+
+    run_statements(program, &program.body, memory);
+    advance_recurrences(memory);
+    react(program, memory);
+    for invariant in &program.invariants {
+        check_invariant(program, invariant, memory);
+    }
+
+    // expect block logic. blocks are checked in order, and unless the program uses `expect all`
+    // only the first one that matches runs
+    let mut matching: Vec<&Statement> = Vec::new();
+    for expect in &program.expect {
+        if calculate_expression(expect.expr.clone().unwrap(), memory).as_bool() {
+            matching.push(expect);
+            if !program.expect_all {
+                break;
             }
         }
     }
+    for expect in &matching {
+        if memory.result.is_some() {
+            break; // returned, the other blocks don't run
+        }
+        run_statements(program, expect.code_block.as_ref().unwrap(), memory);
+    }
+    if memory.result.is_none() {
+        memory.result = memory.yielded.take();
+    }
+
+    let done = !matching.is_empty();
+    if done {
+        run_finally(program, memory);
+    }
+    Rc::make_mut(&mut memory.iteration_ends).push(memory.clock.get());
+    memory.iteration += 1;
+    done
+}
+
+// runs a try or atomic block, undoing every change it made to the histories if it raises an
+// error or its rollback condition holds once it is done. after an error the program carries on
+// after the block
+fn run_atomic(program: &Program, statement: &Statement, memory: &mut Memory) {
+    let recurrences = memory.recurrences.clone();
+    let reacting = memory.reacting.clone();
+    memory.begin_journal();
+
+    let outcome = catch_error(|| {
+        run_statements(program, statement.code_block.as_ref().unwrap(), memory);
+        match &statement.expr {
+            Some(cond) => calculate_expression(cond.clone(), memory).as_bool(),
+            None => false,
+        }
+    });
+
+    match outcome {
+        Ok(false) => memory.commit_journal(),
+        Ok(true) => memory.rollback_journal(),
+        Err(_) => {
+            memory.rollback_journal();
+            // what was being worked on when the error was raised
+            memory.recurrences = recurrences;
+            memory.reacting = reacting;
+            memory.changed.clear();
+        }
+    }
+}
+
+thread_local! {
+    // how many try, atomic and test blocks are running, which report the runtime errors raised
+    // inside them themselves, and whether a test is, which also reports violations
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+    static TESTING: Cell<bool> = const { Cell::new(false) };
+}
+
+// whether a runtime error or violation raised now will be caught, so it shouldn't be reported
+// where it is raised
+pub fn is_caught(payload: &(dyn Any + Send)) -> bool {
+    if payload.is::<Violation>() {
+        return TESTING.with(|testing| testing.get());
+    }
+    CATCHING.with(|catching| catching.get() > 0)
+}
+
+// runs f, turning a runtime error it raises into its message instead of ending the program.
+// any other panic is a bug in the interpreter, which carries on unwinding
+pub fn catch_error<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    CATCHING.with(|catching| catching.set(catching.get() + 1));
+    let outcome = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(catching.get() - 1));
+
+    outcome.map_err(|error| match error.downcast::<RuntimeError>() {
+        Ok(error) => error.0,
+        Err(error) => panic::resume_unwind(error),
+    })
+}
+
+// runs a test block once in memory of its own, returning the error or violation it raised if any
+pub fn run_test(program: &Program, test: &Statement, mut memory: Memory) -> Result<(), String> {
+    TESTING.with(|testing| testing.set(true));
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        catch_error(|| {
+            load_modules(program, &mut memory);
+            declare(program, &mut memory);
+            run_statements(program, test.code_block.as_ref().unwrap(), &mut memory);
+        })
+    }));
+    TESTING.with(|testing| testing.set(false));
+
+    outcome.unwrap_or_else(|error| match error.downcast::<Violation>() {
+        Ok(violation) => Err(violation.0),
+        Err(error) => panic::resume_unwind(error),
+    })
+}
+
+// stops the program if the invariant doesn't hold, reporting the iteration and the last few
+// values of each history it uses
+fn check_invariant(program: &Program, invariant: &Statement, memory: &mut Memory) {
+    let expr = invariant.expr.clone().unwrap();
+    if calculate_expression(expr.clone(), memory).as_bool() {
+        return;
+    }
+    let report = format!(
+        "{}: invariant {} does not hold after iteration {}{}",
+        program.name,
+        expr,
+        memory.iteration,
+        history_report(&expr, memory)
+    );
+    panic::panic_any(Violation(report));
+}
+
+// a line with the last few values of each history the expression uses
+fn history_report(expr: &Expression, memory: &Memory) -> String {
+    let mut report = String::new();
+    for name in expr.histories() {
+        if let Some(history) = memory.cells.get(&name) {
+            report += &format!("\n  {}: {}", name, last_values(&history.borrow(), 5));
+        }
+    }
+    report
+}
+
+// the last n values of a history, after ... if there are more
+fn last_values(history: &History, n: usize) -> String {
+    let start = history.len().saturating_sub(n);
+    let values: Vec<String> = (start..history.len()).map(|i| history.get_past(i).to_string()).collect();
+    if start > 0 {
+        return format!("... {}", values.join(" "));
+    }
+    values.join(" ")
+}
+
+// the finally block runs whenever the program ends, after any expect block
+fn run_finally(program: &Program, memory: &mut Memory) {
+    if let Some(finally) = &program.finally {
+        let result = memory.result.take();
+        run_statements(program, finally.code_block.as_ref().unwrap(), memory);
+        memory.result = result;
+    }
+}
+
+// loops until an expect block fires, returning the value it returned or else the last one yielded
+fn run_until_expect(program: &Program, memory: &mut Memory) -> Option<VariableType> {
+    let mut result: Option<VariableType> = None;
+    loop {
+        let done = step_program(program, memory);
+        if memory.result.is_some() {
+            result = memory.result.take();
+        }
+        if done {
+            return result;
+        }
+    }
 }
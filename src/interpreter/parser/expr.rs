@@ -1,3 +1,10 @@
+use std::fmt;
+
+use super::statement::Statement;
+
+// read-only values about the running program, used as @name
+pub const META_VALUES: [&str; 3] = ["iter", "depth", "program"];
+
 #[derive(Clone, Debug)]
 
 pub enum ExpressionType {
@@ -19,14 +26,36 @@ pub enum ExpressionType {
     EXPONENT,
     UMIN, 
     ABS,
-    PREV(String),
+    PREV(String),    // prev n a, with n in lhs (1 if not given) and the default in rhs
+    FIRST(String),
+    DELTA(String),   // the default in rhs
+    CHANGED(String), // whether a got a value this iteration
     ACCESSOR,
     IDENTIFIER(String),
+    REFERENCE(String), // &name, only as a run argument
     BOOL(bool),
     INTEGER(i64),
     FLOAT(f64),
     STRING(String),
     LEN(String),
+    MAP,
+    INDEX,
+    HAS,
+    CALL(String),
+    RUN(Box<Statement>), // the run statement whose result is the value
+    HISTORY,     // {1, 2, 3}, values in args
+    SLICE(bool), // $a::i..j when true (counting back from the end), i..j::$a when false
+    WINDOW(usize), // window a n, the last n values of a, with an id to keep its statistics by
+    FORK,          // fork a at lhs, a history sharing the values of a up to index lhs
+    NEXT(String), // next g, runs one iteration of the generator g
+    DONE(String), // done g, whether an expect block of g fired
+    META(String), // @iter, @depth or @program, kept up to date by the interpreter
+    AT,           // at lhs { rhs }, rhs evaluated with the histories as they were at iteration lhs
+    // conditions that depend on earlier checks, each with an id to keep its state by
+    EVER(usize),   // ever cond
+    ALWAYS(usize), // always cond
+    SINCE(usize),  // lhs since rhs
+    FOR(usize),    // lhs for rhs iterations
     NONE,
 }
 
@@ -36,6 +65,7 @@ pub struct Expression {
     pub lhs: Option<Box<Expression>>, 
     pub rhs: Option<Box<Expression>>, 
     pub var_name: Option<String>, // used to disambiguate accessor operator
+    pub args: Vec<Box<Expression>>, // map entries (key, value, key, value...), history values and call arguments
 }
 
 impl Expression { 
@@ -45,8 +75,37 @@ impl Expression {
             lhs: lhs, 
             rhs: rhs, 
             var_name: None,
+            args: Vec::new(),
         })
     }
+
+    // the names of the histories the expression uses, each once, in the order they appear
+    pub fn histories(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        self.collect_histories(&mut names);
+        names
+    }
+
+    fn collect_histories(&self, names: &mut Vec<String>) {
+        let name = match &self.exp_type {
+            ExpressionType::IDENTIFIER(s)
+            | ExpressionType::REFERENCE(s)
+            | ExpressionType::PREV(s)
+            | ExpressionType::FIRST(s)
+            | ExpressionType::DELTA(s)
+            | ExpressionType::CHANGED(s)
+            | ExpressionType::LEN(s) => Some(s.clone()),
+            _ => self.var_name.clone(),
+        };
+        if let Some(name) = name {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        for e in self.lhs.iter().chain(self.rhs.iter()).chain(self.args.iter()) {
+            e.collect_histories(names);
+        }
+    }
 }
 
 impl PartialEq for ExpressionType {
@@ -58,3 +117,123 @@ impl PartialEq for ExpressionType {
         std::mem::discriminant(self) != std::mem::discriminant(other)
     }
 }
+
+// written back as source, with operations inside others in parentheses
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lhs = || operand(self.lhs.as_ref().unwrap());
+        let rhs = || operand(self.rhs.as_ref().unwrap());
+        let name = || self.var_name.clone().unwrap_or_default();
+        let default = || match &self.rhs {
+            Some(d) => format!(" default {}", operand(d)),
+            None => String::new(),
+        };
+        let list = |args: &[Box<Expression>]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ");
+
+        if let Some(op) = binary_operator(&self.exp_type) {
+            return write!(f, "{} {} {}", lhs(), op, rhs());
+        }
+        match &self.exp_type {
+            ExpressionType::NOT => write!(f, "not {}", lhs()),
+            ExpressionType::FACTORIAL => write!(f, "!{}", unary_operand(self.lhs.as_ref().unwrap())),
+            ExpressionType::UMIN => write!(f, "-{}", unary_operand(self.lhs.as_ref().unwrap())),
+            ExpressionType::ABS => write!(f, "|{}", unary_operand(self.lhs.as_ref().unwrap())),
+            ExpressionType::PREV(s) => match &self.lhs {
+                Some(n) => write!(f, "prev {} {}{}", operand(n), s, default()),
+                None => write!(f, "prev {}{}", s, default()),
+            },
+            ExpressionType::FIRST(s) => write!(f, "first {}", s),
+            ExpressionType::DELTA(s) => write!(f, "delta {}{}", s, default()),
+            ExpressionType::CHANGED(s) => write!(f, "changed {}", s),
+            ExpressionType::ACCESSOR => match &self.lhs {
+                Some(_) => write!(f, "{}::${}", lhs(), name()),
+                None => write!(f, "${}::{}", name(), rhs()),
+            },
+            ExpressionType::IDENTIFIER(s) => write!(f, "{}", s),
+            ExpressionType::REFERENCE(s) => write!(f, "&{}", s),
+            ExpressionType::BOOL(x) => write!(f, "{}", x),
+            ExpressionType::INTEGER(x) => write!(f, "{}", x),
+            ExpressionType::FLOAT(x) => write!(f, "{:?}", x),
+            ExpressionType::STRING(s) => write!(f, "\"{}\"", s),
+            ExpressionType::LEN(s) => write!(f, "#{}", s),
+            ExpressionType::MAP if self.args.is_empty() => write!(f, "{{:}}"),
+            ExpressionType::MAP => {
+                let entries: Vec<String> =
+                    self.args.chunks(2).map(|kv| format!("{}: {}", kv[0], kv[1])).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            ExpressionType::INDEX => write!(f, "{}[{}]", lhs(), self.rhs.as_ref().unwrap()),
+            ExpressionType::CALL(s) => write!(f, "{}({})", s, list(&self.args)),
+            ExpressionType::RUN(statement) => {
+                let program = match (&statement.sub_program, &statement.program_path) {
+                    (Some(program), _) => program.name.clone(),
+                    (None, Some(path)) => path.to_string_lossy().to_string(),
+                    _ => String::new(),
+                };
+                write!(f, "(run \"{}\"", program)?;
+                if !statement.alt_exps.is_empty() {
+                    write!(f, " with {}", list(&statement.alt_exps))?;
+                }
+                write!(f, ")")
+            }
+            ExpressionType::HISTORY => write!(f, "{{{}}}", list(&self.args)),
+            ExpressionType::SLICE(true) => write!(f, "${}::{}..{}", name(), lhs(), rhs()),
+            ExpressionType::SLICE(false) => write!(f, "{}..{}::${}", lhs(), rhs(), name()),
+            ExpressionType::WINDOW(_) => write!(f, "window {} {}", name(), lhs()),
+            ExpressionType::FORK => write!(f, "fork {} at {}", name(), lhs()),
+            ExpressionType::NEXT(s) => write!(f, "next {}", s),
+            ExpressionType::DONE(s) => write!(f, "done {}", s),
+            ExpressionType::META(s) => write!(f, "@{}", s),
+            ExpressionType::AT => write!(f, "at {} {{ {} }}", lhs(), self.rhs.as_ref().unwrap()),
+            ExpressionType::EVER(_) => write!(f, "ever {}", lhs()),
+            ExpressionType::ALWAYS(_) => write!(f, "always {}", lhs()),
+            ExpressionType::SINCE(_) => write!(f, "{} since {}", lhs(), rhs()),
+            ExpressionType::FOR(_) => write!(f, "{} for {} iterations", lhs(), rhs()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn binary_operator(exp_type: &ExpressionType) -> Option<&'static str> {
+    match exp_type {
+        ExpressionType::ADD => Some("+"),
+        ExpressionType::SUB => Some("-"),
+        ExpressionType::MUL => Some("*"),
+        ExpressionType::DIV => Some("/"),
+        ExpressionType::MOD => Some("%"),
+        ExpressionType::EXPONENT => Some("^"),
+        ExpressionType::EQU => Some("=="),
+        ExpressionType::NEQU => Some("!="),
+        ExpressionType::GTH => Some(">"),
+        ExpressionType::GTHE => Some(">="),
+        ExpressionType::LTH => Some("<"),
+        ExpressionType::LTHE => Some("<="),
+        ExpressionType::AND => Some("and"),
+        ExpressionType::OR => Some("or"),
+        ExpressionType::HAS => Some("has"),
+        _ => None,
+    }
+}
+
+// the operand of !, - or |, in parentheses unless it is a single value
+fn unary_operand(expr: &Expression) -> String {
+    match expr.exp_type {
+        ExpressionType::IDENTIFIER(_)
+        | ExpressionType::INTEGER(_)
+        | ExpressionType::FLOAT(_)
+        | ExpressionType::BOOL(_)
+        | ExpressionType::LEN(_)
+        | ExpressionType::META(_)
+        | ExpressionType::CALL(_) => expr.to_string(),
+        _ => format!("({})", expr),
+    }
+}
+
+// an expression used inside another one, in parentheses if it is an operation
+fn operand(expr: &Expression) -> String {
+    match expr.exp_type {
+        ExpressionType::SINCE(_) | ExpressionType::FOR(_) => format!("({})", expr),
+        _ if binary_operator(&expr.exp_type).is_some() => format!("({})", expr),
+        _ => expr.to_string(),
+    }
+}
@@ -31,9 +31,18 @@ pub fn symbol_analysis(input: &str) -> Option<Vec<Token>> {
         (",", TokenType::COMMA),
         ("::", TokenType::ACCESSOR),
         ("=:", TokenType::COPY),
+        ("=&", TokenType::ALIAS),
+        (":=", TokenType::DEFINE),
         ("#", TokenType::LEN),
         ("--", TokenType::COMMENT),
         ("$", TokenType::DOLLAR),
+        ("[", TokenType::LSQUARE),
+        ("]", TokenType::RSQUARE),
+        (":", TokenType::COLON),
+        ("..", TokenType::DOTDOT),
+        ("=", TokenType::EQUALS),
+        ("&", TokenType::AMPERSAND),
+        ("@", TokenType::AT),
     ]
     .into_iter()
     .collect();
@@ -108,15 +117,22 @@ pub fn symbol_analysis(input: &str) -> Option<Vec<Token>> {
         }
         else if chr.is_alphabetic() || chr == '_' {
             let mut j = i;
-            while j < input.len() - 1 && (chars[j + 1].is_alphanumeric() || chars[j + 1] == '_') {
+            // a `.` followed by a name continues the identifier, so module members read as lib.name
+            let continues = |j: usize| {
+                chars[j + 1].is_alphanumeric()
+                    || chars[j + 1] == '_'
+                    || (chars[j + 1] == '.'
+                        && j + 2 < chars.len()
+                        && (chars[j + 2].is_alphabetic() || chars[j + 2] == '_'))
+            };
+            while j < input.len() - 1 && continues(j) {
                 j += 1;
-                if let Ok(x) = TokenType::from_str(&input[i..j + 1].to_uppercase()) {
-                    token.token_type = x;
-                }
             }
 
-            if token.token_type == TokenType::NONE {
-                token.token_type = TokenType::IDENTIFIER(input[i..j + 1].to_string());
+            // keywords have to match the whole word, otherwise `printer` would lex as `print`
+            match TokenType::from_str(&input[i..j + 1].to_uppercase()) {
+                Ok(x) => token.token_type = x,
+                _ => token.token_type = TokenType::IDENTIFIER(input[i..j + 1].to_string()),
             }
 
             i = j;
@@ -130,7 +146,8 @@ pub fn symbol_analysis(input: &str) -> Option<Vec<Token>> {
             };
 
             let mut j = digit_scan(i, &chars);
-            if j < chars.len() && chars[j] == '.' {
+            // only a dot followed by a digit makes a float, so 0..3 is a range
+            if j + 1 < chars.len() && chars[j] == '.' && chars[j + 1].is_numeric() {
                 j = digit_scan(j, &chars);
                 token.token_type = TokenType::FLOAT(input[i..j].parse().unwrap());
             } else {
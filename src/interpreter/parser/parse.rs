@@ -1,11 +1,23 @@
-use std::collections::HashMap;
 use std::path::PathBuf;
-use std::{fs, process};
+use std::rc::Rc;
+use std::{env, fs};
 
-use super::expr::{Expression, ExpressionType};
+use super::expr::{Expression, ExpressionType, META_VALUES};
+use crate::interpreter::{builtins, stdlib};
+use crate::user_options::USER_OPTIONS;
 use super::lexer::symbol_analysis;
-use super::parsing_types::{Token, TokenType};
-use super::statement::{Program, Statement, StatementType};
+use super::parsing_types::{Token, TokenType, RESERVED_WORDS};
+use super::statement::{Parameter, ParameterMode, Program, ProgramCache, Statement, StatementType};
+
+// words that only follow another part of a construct (fork b at i, cond for n iterations, prev a
+// default x, ...). a word that starts a construct isn't one when it is followed by one of these
+const FOLLOWING_WORDS: [&str; 7] = ["as", "at", "change", "default", "for", "from", "iterations"];
+
+// programs are cached by canonical path, so the same file reached from different directories is
+// parsed once. paths that don't exist (preloaded programs) are used as they are
+pub fn cache_key(path: &PathBuf) -> PathBuf {
+    fs::canonicalize(path).unwrap_or(path.clone())
+}
 
 pub struct Parser<'a> {
     current_token: Token,
@@ -14,14 +26,17 @@ pub struct Parser<'a> {
     prog: Program,
     stat: Statement,
     directory: PathBuf,
-    prog_cache: &'a mut HashMap<String, Box<Program>>,
+    prog_cache: &'a mut ProgramCache,
+    file_chain: Vec<PathBuf>, // files currently being parsed, used to find import cycles
+    in_expect: bool,
+    in_begin: bool,
+    next_id: usize, // id given to the next statement that needs one
 }
 impl<'a> Parser<'a> {
     pub fn new(
         tokens: Vec<Token>,
-        prog_cache: &'a mut HashMap<String, Box<Program>>,
+        prog_cache: &'a mut ProgramCache,
         file_path: &'a PathBuf,
-        top_level: bool
     ) -> Parser<'a> {
         let mut directory = file_path.clone();
         let mut tokens = tokens.clone(); // TODO: don't make the parse require a newline at the end
@@ -35,10 +50,14 @@ impl<'a> Parser<'a> {
             current_token: tokens[0].clone(),
             tokens: tokens,
             index: 0,
-            prog: Program::new(String::from(file_path.to_string_lossy()), top_level),
+            prog: Program::new(String::from(file_path.to_string_lossy())),
             stat: Statement::new(),
             directory: directory,
             prog_cache: prog_cache,
+            file_chain: vec![cache_key(file_path)],
+            in_expect: false,
+            in_begin: false,
+            next_id: 0,
         }
     }
 
@@ -48,19 +67,28 @@ impl<'a> Parser<'a> {
         return &self.prog;
     }
 
-    fn error_missing_token(&self, t: TokenType) {
-        eprintln!(
+    // parse errors are raised like runtime errors, so a host parsing several files can catch
+    // the one that fails and go on with the rest
+    fn error_missing_token(&self, t: TokenType) -> ! {
+        self.check_reserved(&self.current_token.token_type);
+        runtime_error!(
             "line {}: expected {:?} got {:?}",
             self.current_token.line + 1,
             t,
             self.current_token.token_type
         );
-        process::abort();
     }
 
-    fn error_custom(&self, msg: &str) {
-        eprintln!("line {}: {}", self.current_token.line + 1, msg);
-        process::abort();
+    fn error_custom(&self, msg: &str) -> ! {
+        runtime_error!("line {}: {}", self.current_token.line + 1, msg);
+    }
+
+    // a keyword where a name was meant is the likely reason the program doesn't parse
+    fn check_reserved(&self, t: &TokenType) {
+        let word = format!("{:?}", t).to_lowercase();
+        if RESERVED_WORDS.contains(&word.as_str()) {
+            self.error_custom(format!("`{}` is a reserved word", word).as_str());
+        }
     }
 
     fn next_token(&mut self) -> Token {
@@ -81,6 +109,77 @@ impl<'a> Parser<'a> {
         self.tokens[self.index + i].clone()
     }
 
+    // whether the token i ahead is the identifier word, which is a keyword only where the
+    // parser looks for it. like keywords, it can be written in any case
+    fn word_ahead(&self, i: usize, word: &str) -> bool {
+        match self.tokens.get(self.index + i).map(|t| &t.token_type) {
+            Some(TokenType::IDENTIFIER(s)) => s.eq_ignore_ascii_case(word),
+            _ => false,
+        }
+    }
+
+    // whether the token i ahead is a name, and not a word that continues a construct
+    fn name_ahead(&self, i: usize) -> bool {
+        match self.tokens.get(self.index + i).map(|t| &t.token_type) {
+            Some(TokenType::IDENTIFIER(s)) => !FOLLOWING_WORDS.iter().any(|w| s.eq_ignore_ascii_case(w)),
+            _ => false,
+        }
+    }
+
+    fn string_ahead(&self, i: usize) -> bool {
+        matches!(self.tokens.get(self.index + i).map(|t| &t.token_type), Some(TokenType::STRING(_)))
+    }
+
+    fn line_ends_ahead(&self, i: usize) -> bool {
+        matches!(self.tokens.get(self.index + i).map(|t| &t.token_type), None | Some(TokenType::NEWLINE))
+    }
+
+    // whether the current token starts an operand that can't follow a name, so the word before
+    // it is a keyword rather than a name
+    fn operand_ahead(&self) -> bool {
+        match &self.current_token.token_type {
+            TokenType::IDENTIFIER(_) => self.name_ahead(0),
+            t => matches!(
+                t,
+                TokenType::INTEGER(_)
+                    | TokenType::FLOAT(_)
+                    | TokenType::STRING(_)
+                    | TokenType::TRUE
+                    | TokenType::FALSE
+                    | TokenType::LPAREN
+                    | TokenType::AT
+                    | TokenType::DOLLAR
+                    | TokenType::LEN
+                    | TokenType::PREV
+                    | TokenType::NOT
+                    | TokenType::VERTICALBAR
+            ),
+        }
+    }
+
+    fn accept_word(&mut self, word: &str) -> bool {
+        if self.word_ahead(0, word) {
+            self.next_token();
+            return true;
+        }
+        false
+    }
+
+    fn expect_word(&mut self, word: &str) {
+        if !self.accept_word(word) {
+            self.error_custom(format!("expected {} got {:?}", word, self.current_token.token_type).as_str());
+        }
+    }
+
+    // word followed by a name, as in next g or window a 5, where word can't be a name itself
+    fn accept_prefix(&mut self, word: &str) -> bool {
+        if self.word_ahead(0, word) && self.name_ahead(1) {
+            self.next_token();
+            return true;
+        }
+        false
+    }
+
     fn accept(&mut self, t: TokenType) -> bool {
         if self.current_token.equals(t) {
             self.next_token();
@@ -94,7 +193,6 @@ impl<'a> Parser<'a> {
             return true;
         }
         self.error_missing_token(t);
-        return false;
     }
 
     fn expect_identifier(&mut self) -> Option<String> {
@@ -110,14 +208,49 @@ impl<'a> Parser<'a> {
             return None;
         }
         self.error_missing_token(TokenType::IDENTIFIER(String::from("")));
-        None
+    }
+
+    // identifiers that are written to can not belong to a module or be a const parameter
+    fn expect_writable_identifier(&mut self) -> Option<String> {
+        let ident = self.expect_identifier();
+        if let Some(name) = &ident {
+            if name.contains('.') {
+                self.error_custom(format!("cannot write to {}, module values are read-only", name).as_str());
+            }
+            let is_const = |p: &Parameter| p.name == *name && p.mode == ParameterMode::CONST;
+            if self.prog.parameters.iter().flatten().any(is_const) {
+                self.error_custom(format!("cannot write to {}, it is a const parameter", name).as_str());
+            }
+        }
+        ident
     }
 
     fn take(&mut self) {
+        self.accept(TokenType::NEWLINE); // comments or blank lines before take
         if self.accept(TokenType::TAKE) {
-            let mut params: Vec<String> = Vec::new();
+            let mut params: Vec<Parameter> = Vec::new();
             loop {
-                params.push(self.expect_identifier().unwrap());
+                let mode = if self.accept_prefix("ref") {
+                    ParameterMode::REF
+                } else if self.accept_prefix("val") {
+                    ParameterMode::VAL
+                } else if self.accept(TokenType::CONST) {
+                    ParameterMode::CONST
+                } else {
+                    ParameterMode::NONE
+                };
+                let name = self.expect_identifier().unwrap();
+                let default = if self.accept(TokenType::EQUALS) {
+                    Some(self.expr())
+                } else {
+                    None
+                };
+                if default.is_none() && params.iter().any(|p| p.default.is_some()) {
+                    self.error_custom(
+                        format!("parameter {} needs a default since it follows one with a default", name).as_str(),
+                    );
+                }
+                params.push(Parameter { name, mode, default });
 
                 if !self.accept(TokenType::COMMA) {
                     break;
@@ -135,7 +268,21 @@ impl<'a> Parser<'a> {
 
             match self.stat.statement_type {
                 StatementType::BEGIN => self.prog.begin = Some(self.stat.clone()),
+                StatementType::EXPECT if self.stat.expr.is_none() => self.prog.expect_all = true,
                 StatementType::EXPECT => self.prog.expect.push(self.stat.clone()),
+                StatementType::FINALLY => self.prog.finally = Some(self.stat.clone()),
+                StatementType::REQUIRE => self.prog.require.push(self.stat.clone()),
+                StatementType::DEFINE => self.prog.recurrences.push(self.stat.clone()),
+                StatementType::DERIVE => self.prog.derived.push(self.stat.clone()),
+                StatementType::ON => self.prog.triggers.push(self.stat.clone()),
+                StatementType::INVARIANT => self.prog.invariants.push(self.stat.clone()),
+                StatementType::TEST => self.prog.tests.push(self.stat.clone()),
+                StatementType::USE => {
+                    let namespace = self.stat.var_name.clone().unwrap();
+                    let module = self.stat.sub_program.clone().unwrap();
+                    self.prog.modules.push((namespace, module));
+                }
+                StatementType::NONE => {} // blank line, e.g. a trailing newline at the end of the file
                 _ => self.prog.add(self.stat.clone()),
             }
             self.stat.reset();
@@ -151,19 +298,59 @@ impl<'a> Parser<'a> {
         let mut code_block: Vec<Statement> = Vec::new();
         self.expect(TokenType::LBRACKET);
         self.expect(TokenType::NEWLINE);
-        loop {
+        while !self.accept(TokenType::RBRACKET) {
             self.statement();
+            match self.stat.statement_type {
+                StatementType::USE | StatementType::BEGIN | StatementType::EXPECT
+                | StatementType::FINALLY | StatementType::REQUIRE | StatementType::DEFINE | StatementType::DERIVE
+                | StatementType::ON | StatementType::INVARIANT | StatementType::TEST => self.error_custom(
+                    format!("{:?} is only allowed at the top level of a file", self.stat.statement_type)
+                        .to_lowercase()
+                        .as_str(),
+                ),
+                _ => {}
+            }
             self.expect(TokenType::NEWLINE);
             code_block.push(self.stat.clone());
             self.stat.reset();
-            if self.accept(TokenType::RBRACKET) {
-                break;
-            }
         }
         self.stat = old_stat;
         return code_block;
     }
 
+    // the condition of an expect, require or if, or a parenthesized expression, which can use
+    // the temporal qualifiers: ever c, always c, c for n iterations and c since d
+    fn condition(&mut self) -> Box<Expression> {
+        let mut lhs = self.temporal();
+        while self.accept(TokenType::SINCE) {
+            let rhs = self.temporal();
+            let id = self.new_id();
+            lhs = Expression::new(ExpressionType::SINCE(id), Some(lhs), Some(rhs));
+        }
+        lhs
+    }
+
+    fn temporal(&mut self) -> Box<Expression> {
+        if self.accept(TokenType::EVER) {
+            let cond = self.temporal();
+            let id = self.new_id();
+            return Expression::new(ExpressionType::EVER(id), Some(cond), None);
+        } else if self.accept(TokenType::ALWAYS) {
+            let cond = self.temporal();
+            let id = self.new_id();
+            return Expression::new(ExpressionType::ALWAYS(id), Some(cond), None);
+        }
+
+        let cond = self.expr();
+        if self.accept_word("for") {
+            let count = self.epxr_add();
+            self.expect_word("iterations");
+            let id = self.new_id();
+            return Expression::new(ExpressionType::FOR(id), Some(cond), Some(count));
+        }
+        cond
+    }
+
     fn expr(&mut self) -> Box<Expression> {
         let mut lhs = self.expr_comp();
         while self.current_token.equals(TokenType::AND) || self.current_token.equals(TokenType::OR)
@@ -187,6 +374,7 @@ impl<'a> Parser<'a> {
             || self.current_token.equals(TokenType::NOTEQUALOP)
             || self.current_token.equals(TokenType::LTHANOP)
             || self.current_token.equals(TokenType::LETHANOP)
+            || self.current_token.equals(TokenType::HAS)
         {
             let old = self.next_token();
             let rhs = self.epxr_add();
@@ -209,6 +397,9 @@ impl<'a> Parser<'a> {
                 TokenType::LETHANOP => {
                     lhs = Expression::new(ExpressionType::LTHE, Some(lhs), Some(rhs))
                 }
+                TokenType::HAS => {
+                    lhs = Expression::new(ExpressionType::HAS, Some(lhs), Some(rhs))
+                }
 
                 _ => {}
             }
@@ -272,6 +463,14 @@ impl<'a> Parser<'a> {
         return lhs;
     }
 
+    // `default value` after prev or delta, used when the history is too short
+    fn default_value(&mut self) -> Option<Box<Expression>> {
+        if self.accept_word("default") {
+            return Some(self.unary_fact());
+        }
+        None
+    }
+
     fn unary_fact(&mut self) -> Box<Expression> {
         if self.accept(TokenType::NOT) {
             return Expression::new(ExpressionType::NOT, Some(self.factor()), None);
@@ -282,14 +481,50 @@ impl<'a> Parser<'a> {
         } else if self.accept(TokenType::VERTICALBAR) {
             return Expression::new(ExpressionType::ABS, Some(self.factor()), None);
         } else if self.accept(TokenType::PREV) {
-            return Expression::new(
-                ExpressionType::PREV(self.expect_identifier().unwrap()),
-                None,
-                None,
-            );
+            // prev a, prev 3 a or prev (n) a
+            let count = if self.current_token.equals(TokenType::INTEGER(0))
+                || self.current_token.equals(TokenType::LPAREN)
+            {
+                Some(self.atom())
+            } else {
+                None
+            };
+            let name = self.expect_identifier().unwrap();
+            let default = self.default_value();
+            return Expression::new(ExpressionType::PREV(name), count, default);
+        } else if self.accept_prefix("first") {
+            let name: Option<String> = self.expect_identifier();
+            return Expression::new(ExpressionType::FIRST(name.unwrap()), None, None);
+        } else if self.accept_prefix("delta") {
+            let name = self.expect_identifier().unwrap();
+            let default = self.default_value();
+            return Expression::new(ExpressionType::DELTA(name), None, default);
+        } else if self.accept_prefix("window") {
+            let name = self.expect_identifier();
+            let size = self.atom();
+            let id = self.new_id();
+            let mut window = Expression::new(ExpressionType::WINDOW(id), Some(size), None);
+            window.var_name = name;
+            return window;
+        } else if self.accept(TokenType::FORK) {
+            let name = self.expect_identifier();
+            self.expect_word("at");
+            let index = self.epxr_add();
+            let mut fork = Expression::new(ExpressionType::FORK, Some(index), None);
+            fork.var_name = name;
+            return fork;
+        } else if self.accept(TokenType::CHANGED) {
+            let name: Option<String> = self.expect_identifier();
+            return Expression::new(ExpressionType::CHANGED(name.unwrap()), None, None);
         } else if self.accept(TokenType::LEN) {
             let name: Option<String> = self.expect_identifier();
             return Expression::new(ExpressionType::LEN(name.unwrap()), None, None);
+        } else if self.accept_prefix("next") {
+            let name: Option<String> = self.expect_identifier();
+            return Expression::new(ExpressionType::NEXT(name.unwrap()), None, None);
+        } else if self.accept_prefix("done") {
+            let name: Option<String> = self.expect_identifier();
+            return Expression::new(ExpressionType::DONE(name.unwrap()), None, None);
         }
         return self.accessor_factor();
     }
@@ -298,22 +533,38 @@ impl<'a> Parser<'a> {
         let mut ident: Option<String> = None;
         let mut lhs: Option<Box<Expression>> = None;
         let mut rhs: Option<Box<Expression>> = None;
+        let mut range_end: Option<Box<Expression>> = None;
         if self.accept(TokenType::DOLLAR) {
             ident = self.expect_identifier();
         } else {
             lhs = Some(self.factor());
+            if self.accept(TokenType::DOTDOT) {
+                range_end = Some(self.factor());
+            }
         }
         while self.accept(TokenType::ACCESSOR) {
             if self.accept(TokenType::DOLLAR) {
-                if !matches!(ident.clone(), None) {
+                if ident.is_some() {
                     self.error_custom("multiple histories marked as source");
                 }
                 ident = self.expect_identifier();
+                if let Some(end) = range_end {
+                    // i..j::$a
+                    return self.slice(ident, lhs.unwrap(), end, false);
+                }
             } else {
                 rhs = Some(self.factor());
+                if self.accept(TokenType::DOTDOT) {
+                    // $a::i..j
+                    let end = self.factor();
+                    if ident.is_none() {
+                        self.error_custom("a range must be taken from a history marked as source ($a::i..j)");
+                    }
+                    return self.slice(ident, rhs.unwrap(), end, true);
+                }
             }
 
-            if matches!(ident, None) {
+            if ident.is_none() {
                 self.error_custom(
                     "one side of must be marked an identifier marked as source ($a::1, i::$a, etc)",
                 );
@@ -323,35 +574,148 @@ impl<'a> Parser<'a> {
                 lhs: lhs,
                 rhs: rhs.clone(),
                 var_name: ident.clone(),
+                args: Vec::new(),
             };
             lhs = Some(Box::new(expr));
         }
+        if range_end.is_some() {
+            self.error_custom("a range must be taken from a history (i..j::$a or $a::i..j)");
+        }
         return lhs.unwrap();
     }
 
+    fn slice(
+        &mut self,
+        ident: Option<String>,
+        start: Box<Expression>,
+        end: Box<Expression>,
+        from_end: bool,
+    ) -> Box<Expression> {
+        let mut slice = Expression::new(ExpressionType::SLICE(from_end), Some(start), Some(end));
+        slice.var_name = ident;
+        slice
+    }
+
     fn factor(&mut self) -> Box<Expression> {
+        let mut lhs = self.atom();
+        while self.accept(TokenType::LSQUARE) {
+            let key = self.expr();
+            self.expect(TokenType::RSQUARE);
+            lhs = Expression::new(ExpressionType::INDEX, Some(lhs), Some(key));
+        }
+        lhs
+    }
+
+    fn atom(&mut self) -> Box<Expression> {
         match self.next_token().token_type {
-            TokenType::IDENTIFIER(s) => Expression::new(ExpressionType::IDENTIFIER(s), None, None),
+            // at k { expr }, where at followed by anything that can't come after a name
+            TokenType::IDENTIFIER(s) if s.eq_ignore_ascii_case("at") && self.operand_ahead() => {
+                let iteration = self.expr();
+                self.expect(TokenType::LBRACKET);
+                self.accept(TokenType::NEWLINE);
+                let exp = self.condition();
+                self.accept(TokenType::NEWLINE);
+                self.expect(TokenType::RBRACKET);
+                Expression::new(ExpressionType::AT, Some(iteration), Some(exp))
+            }
+            TokenType::IDENTIFIER(s) => {
+                if self.current_token.equals(TokenType::LPAREN) {
+                    return self.call(s);
+                }
+                Expression::new(ExpressionType::IDENTIFIER(s), None, None)
+            }
             TokenType::INTEGER(x) => Expression::new(ExpressionType::INTEGER(x), None, None),
             TokenType::FLOAT(x) => Expression::new(ExpressionType::FLOAT(x), None, None),
             TokenType::TRUE => Expression::new(ExpressionType::BOOL(true), None, None),
             TokenType::FALSE => Expression::new(ExpressionType::BOOL(false), None, None),
+            TokenType::STRING(s) => Expression::new(ExpressionType::STRING(s), None, None),
+            TokenType::LBRACKET => self.map_literal(),
+            TokenType::AT => {
+                let name = self.expect_identifier().unwrap();
+                if !META_VALUES.contains(&name.as_str()) {
+                    self.error_custom(format!("unknown value @{}", name).as_str());
+                }
+                Expression::new(ExpressionType::META(name), None, None)
+            }
+            TokenType::RUN => {
+                // parse the call as its own statement, keeping the one we're in the middle of
+                let old_stat = self.stat.clone();
+                self.stat = Statement::new();
+                self.parse_stmt_call();
+                let call = self.stat.clone();
+                self.stat = old_stat;
+                Expression::new(ExpressionType::RUN(Box::new(call)), None, None)
+            }
 
             TokenType::LPAREN => {
-                let exp = self.expr();
+                let exp = self.condition();
                 self.expect(TokenType::RPAREN);
                 return exp;
             }
 
-            _ => {
+            t => {
+                self.check_reserved(&t);
                 self.error_custom(
                     format!("expression error for token {:?}", self.current_token).as_str(),
                 );
-                return Expression::new(ExpressionType::NONE, None, None);
             }
         }
     }
 
+    // {key: value, ...}, with {:} as the empty map, or a history literal {value, ...}
+    fn map_literal(&mut self) -> Box<Expression> {
+        let mut map = Expression::new(ExpressionType::MAP, None, None);
+        if self.accept(TokenType::COLON) {
+            self.expect(TokenType::RBRACKET);
+            return map;
+        }
+
+        let first = self.expr();
+        if !self.current_token.equals(TokenType::COLON) {
+            return self.history_literal(first);
+        }
+        map.args.push(first);
+        self.expect(TokenType::COLON);
+        map.args.push(self.expr());
+
+        while self.accept(TokenType::COMMA) {
+            map.args.push(self.expr());
+            self.expect(TokenType::COLON);
+            map.args.push(self.expr());
+        }
+        self.expect(TokenType::RBRACKET);
+        map
+    }
+
+    fn history_literal(&mut self, first: Box<Expression>) -> Box<Expression> {
+        let mut history = Expression::new(ExpressionType::HISTORY, None, None);
+        history.args.push(first);
+        while self.accept(TokenType::COMMA) {
+            history.args.push(self.expr());
+        }
+        self.expect(TokenType::RBRACKET);
+        history
+    }
+
+    fn call(&mut self, name: String) -> Box<Expression> {
+        if !builtins::is_builtin(&name) {
+            self.error_custom(format!("unknown function {}", name).as_str());
+        }
+        self.expect(TokenType::LPAREN);
+        let mut call = Expression::new(ExpressionType::CALL(name), None, None);
+        if self.accept(TokenType::RPAREN) {
+            return call;
+        }
+        loop {
+            call.args.push(self.expr());
+            if !self.accept(TokenType::COMMA) {
+                break;
+            }
+        }
+        self.expect(TokenType::RPAREN);
+        call
+    }
+
     fn parse_string(&mut self) -> String {
         match self.current_token.token_type.clone() {
             TokenType::STRING(s) => {
@@ -363,13 +727,16 @@ impl<'a> Parser<'a> {
                 self.error_custom(
                     format!("expected STRING, got {:?}", self.current_token).as_str(),
                 );
-                process::abort();
             }
         }
     }
 
     fn statement(&mut self) {
         self.stat.reset();
+        let assigned = [TokenType::ASSIGNMENT, TokenType::COPY, TokenType::ALIAS, TokenType::DEFINE];
+        if self.tokens.get(self.index + 1).is_some_and(|t| assigned.contains(&t.token_type)) {
+            self.check_reserved(&self.current_token.token_type);
+        }
         if self
             .current_token
             .equals(TokenType::IDENTIFIER(String::from("")))
@@ -382,10 +749,47 @@ impl<'a> Parser<'a> {
             && self.ahead(1).equals(TokenType::COPY)
         {
             self.parse_stmt_copy();
+        } else if self
+            .current_token
+            .equals(TokenType::IDENTIFIER(String::from("")))
+            && self.ahead(1).equals(TokenType::ALIAS)
+        {
+            self.parse_stmt_alias();
+        } else if self
+            .current_token
+            .equals(TokenType::IDENTIFIER(String::from("")))
+            && self.ahead(1).equals(TokenType::DEFINE)
+        {
+            self.parse_stmt_define();
+        } else if self
+            .current_token
+            .equals(TokenType::IDENTIFIER(String::from("")))
+            && self.ahead(1).equals(TokenType::LSQUARE)
+        {
+            self.parse_stmt_insert();
+        } else if self.accept(TokenType::DERIVE) {
+            self.parse_stmt_derive();
+        } else if self.word_ahead(0, "on") && self.word_ahead(1, "change") {
+            self.next_token();
+            self.parse_stmt_on();
         } else if self.accept(TokenType::BEGIN) {
             self.parse_stmt_begin();
         } else if self.accept(TokenType::EXPECT) {
             self.parse_stmt_expect();
+        } else if self.accept(TokenType::FINALLY) {
+            self.parse_stmt_finally();
+        } else if self.accept(TokenType::REQUIRE) {
+            self.parse_stmt_require();
+        } else if self.accept(TokenType::ASSERT) {
+            self.parse_stmt_assert();
+        } else if self.word_ahead(0, "test") && self.string_ahead(1) {
+            self.next_token();
+            self.stat.set_type(StatementType::TEST);
+            self.stat.var_name = Some(self.parse_string());
+            self.stat.code_block = Some(self.code_block());
+        } else if self.accept(TokenType::INVARIANT) {
+            self.stat.set_type(StatementType::INVARIANT);
+            self.stat.expr = Some(self.condition());
         } else if self.accept(TokenType::REVEAL) {
             self.parse_stmt_reveal();
         } else if self.accept(TokenType::PRINT) {
@@ -394,34 +798,178 @@ impl<'a> Parser<'a> {
             self.parse_stmt_tprint();
         } else if self.accept(TokenType::IF) {
             self.parse_stmt_if();
+        } else if self.accept(TokenType::TRY) {
+            self.parse_stmt_atomic(true);
+        } else if self.accept(TokenType::ATOMIC) {
+            self.parse_stmt_atomic(false);
         } else if self.accept(TokenType::RUN) {
             self.parse_stmt_call();
+        } else if self.word_ahead(0, "start") && (self.string_ahead(1) || self.name_ahead(1)) {
+            self.next_token();
+            self.parse_stmt_start();
+        } else if self.accept(TokenType::USE) {
+            self.parse_stmt_use();
+        } else if self.accept(TokenType::RETURN) {
+            self.parse_stmt_return(false);
+        } else if self.accept(TokenType::YIELD) {
+            self.parse_stmt_return(true);
         }
     }
 
     fn parse_stmt_assign(&mut self) {
         self.stat.set_type(StatementType::ASSIGN);
-        self.stat.var_name = self.expect_identifier();
+        self.stat.var_name = self.expect_writable_identifier();
+        self.expect(TokenType::ASSIGNMENT);
+        self.stat.expr = Some(self.expr());
+    }
+
+    fn parse_stmt_insert(&mut self) {
+        self.stat.set_type(StatementType::INSERT);
+        self.stat.var_name = self.expect_writable_identifier();
+        self.expect(TokenType::LSQUARE);
+        let key = self.expr();
+        self.stat.alt_exps.push(key);
+        self.expect(TokenType::RSQUARE);
         self.expect(TokenType::ASSIGNMENT);
         self.stat.expr = Some(self.expr());
     }
 
     fn parse_stmt_copy(&mut self) {
         self.stat.set_type(StatementType::COPY);
-        self.stat.var_name = self.expect_identifier();
+        self.stat.var_name = self.expect_writable_identifier();
         self.expect(TokenType::COPY);
         self.stat.alt_var_name = self.expect_identifier();
     }
 
+    fn parse_stmt_alias(&mut self) {
+        self.stat.set_type(StatementType::ALIAS);
+        self.stat.var_name = self.expect_writable_identifier();
+        self.expect(TokenType::ALIAS);
+        self.stat.alt_var_name = self.expect_writable_identifier();
+    }
+
+    // fib := {0, 1} then prev fib + fib
+    fn parse_stmt_define(&mut self) {
+        self.stat.set_type(StatementType::DEFINE);
+        let name = self.expect_writable_identifier().unwrap();
+        if self.prog.recurrences.iter().any(|r| r.var_name.as_ref() == Some(&name)) {
+            self.error_custom(format!("{} is already defined by a recurrence", name).as_str());
+        }
+        self.expect(TokenType::DEFINE);
+        let seeds = self.expr();
+        if seeds.exp_type != ExpressionType::HISTORY {
+            self.error_custom(
+                format!("{} := needs its first values in braces, as in {} := {{0, 1}} then ...", name, name).as_str(),
+            );
+        }
+        self.expect(TokenType::THEN);
+        self.stat.var_name = Some(name);
+        self.stat.alt_exps = seeds.args.clone();
+        self.stat.expr = Some(self.expr());
+    }
+
+    // derive total from a, b = a + b
+    fn parse_stmt_derive(&mut self) {
+        self.stat.set_type(StatementType::DERIVE);
+        let name = self.expect_writable_identifier().unwrap();
+        if self.prog.derived.iter().any(|d| d.var_name.as_ref() == Some(&name)) {
+            self.error_custom(format!("{} is already derived", name).as_str());
+        }
+        self.expect_word("from");
+        loop {
+            let source = self.expect_identifier().unwrap();
+            self.stat.arg_names.push(Some(source));
+            if !self.accept(TokenType::COMMA) {
+                break;
+            }
+        }
+        self.expect(TokenType::EQUALS);
+        self.stat.var_name = Some(name.clone());
+        self.stat.expr = Some(self.expr());
+
+        let derived = self.stat.clone();
+        if let Some(cycle) = self.derive_cycle(&derived, vec![name]) {
+            self.error_custom(format!("derived histories depend on each other: {}", cycle.join(" -> ")).as_str());
+        }
+    }
+
+    // follows the sources of a derived history back through the other derived histories,
+    // returning the chain of names if it leads back to one already in it
+    fn derive_cycle(&self, derived: &Statement, chain: Vec<String>) -> Option<Vec<String>> {
+        for source in derived.arg_names.iter().flatten() {
+            let mut chain = chain.clone();
+            chain.push(source.clone());
+            if chain[0] == *source {
+                return Some(chain);
+            }
+            let next = self.prog.derived.iter().find(|d| d.var_name.as_ref() == Some(source));
+            // the ones parsed before can't form a cycle of their own, so this ends
+            if let Some(next) = next {
+                if let Some(cycle) = self.derive_cycle(next, chain) {
+                    return Some(cycle);
+                }
+            }
+        }
+        None
+    }
+
+    // on change a { ... }
+    fn parse_stmt_on(&mut self) {
+        self.stat.set_type(StatementType::ON);
+        self.expect_word("change");
+        self.stat.var_name = self.expect_identifier();
+        self.stat.code_block = Some(self.code_block());
+    }
+
     fn parse_stmt_begin(&mut self) {
         self.stat.set_type(StatementType::BEGIN);
+        self.in_begin = true;
         self.stat.code_block = Some(self.code_block());
+        self.in_begin = false;
     }
 
     fn parse_stmt_expect(&mut self) {
         self.stat.set_type(StatementType::EXPECT);
+        if self.word_ahead(0, "all") && self.line_ends_ahead(1) {
+            self.next_token();
+            // `expect all` on its own line, an expect without a condition or block
+            return;
+        }
+        self.stat.expr = Some(self.condition());
+        self.in_expect = true;
+        self.stat.code_block = Some(self.code_block());
+        self.in_expect = false;
+    }
+
+    fn parse_stmt_finally(&mut self) {
+        self.stat.set_type(StatementType::FINALLY);
+        self.stat.code_block = Some(self.code_block());
+    }
+
+    fn parse_stmt_require(&mut self) {
+        self.stat.set_type(StatementType::REQUIRE);
+        self.stat.expr = Some(self.condition());
+    }
+
+    // assert cond or assert cond, "message"
+    fn parse_stmt_assert(&mut self) {
+        self.stat.set_type(StatementType::ASSERT);
+        self.stat.expr = Some(self.condition());
+        if self.accept(TokenType::COMMA) {
+            self.stat.alt_var_name = Some(self.parse_string());
+        }
+    }
+
+    fn parse_stmt_return(&mut self, is_yield: bool) {
+        if is_yield && self.in_begin {
+            self.error_custom("a program can't yield a value from its begin block");
+        } else if !is_yield && !self.in_expect {
+            self.error_custom("a program can only return a value from an expect block");
+        }
+        // in an expect block yield is another way to write return
+        let is_yield = is_yield && !self.in_expect;
+        self.stat.set_type(if is_yield { StatementType::YIELD } else { StatementType::RETURN });
         self.stat.expr = Some(self.expr());
-        self.stat.code_block = Some(self.code_block())
     }
 
     fn parse_stmt_reveal(&mut self) {
@@ -459,11 +1007,11 @@ impl<'a> Parser<'a> {
 
     fn parse_stmt_if(&mut self) {
         self.stat.set_type(StatementType::IF);
-        self.stat.expr = Some(self.expr());
+        self.stat.expr = Some(self.condition());
         self.stat.code_block = Some(self.code_block());
 
         while self.accept(TokenType::ELIF) {
-            let exp = self.expr();
+            let exp = self.condition();
             let block = self.code_block();
             self.stat.alt_exps.push(exp);
             self.stat.alt_code_blocks.push(block);
@@ -475,45 +1023,159 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // try { ... } rollback if cond, or atomic { ... } which only rolls back on an error
+    fn parse_stmt_atomic(&mut self, is_try: bool) {
+        self.stat.set_type(StatementType::ATOMIC);
+        self.stat.code_block = Some(self.code_block());
+        if is_try && self.accept(TokenType::ROLLBACK) {
+            self.expect(TokenType::IF);
+            self.stat.expr = Some(self.condition());
+        }
+    }
+
+    // looks for file_name in the directory of the current file, then in each -I directory,
+    // then in each directory of SEQUENCE_PATH
+    fn find_file(&self, file_name: &str) -> Option<PathBuf> {
+        let mut search_path: Vec<PathBuf> = vec![self.directory.clone()];
+        search_path.extend(USER_OPTIONS.lock().unwrap().include_dirs.iter().cloned());
+        if let Some(paths) = env::var_os("SEQUENCE_PATH") {
+            search_path.extend(env::split_paths(&paths));
+        }
+
+        search_path
+            .into_iter()
+            .map(|directory| directory.join(file_name))
+            .find(|path| path.is_file() || self.prog_cache.contains_key(path)) // preloaded
+    }
+
+    // where file_name is found, along with its key in the cache
+    fn program_path(&self, file_name: &str) -> (PathBuf, PathBuf) {
+        if file_name.starts_with("std:") {
+            return (PathBuf::from(file_name), PathBuf::from(file_name));
+        }
+        let path = self
+            .find_file(file_name)
+            .unwrap_or_else(|| self.error_custom(&format!("could not find file: {}", file_name)));
+        let key = cache_key(&path);
+        (path, key)
+    }
+
+    // parses file_name, either a program from the standard library (std:name) or a file on the search path
+    fn load_program(&mut self, file_name: &str, is_import: bool) -> Rc<Program> {
+        let (new_directory, canonical) = self.program_path(file_name);
+        if let Some(prog) = self.prog_cache.get(&canonical) {
+            return prog.clone();
+        }
+
+        let buf = match file_name.strip_prefix("std:") {
+            Some(name) => match stdlib::source(name) {
+                Some(buf) => buf.to_string(),
+                None => self.error_custom(&format!("no program named {} in the standard library", name)),
+            },
+            None => fs::read_to_string(&new_directory)
+                .unwrap_or_else(|_| self.error_custom(&format!("could not read file: {}", file_name))),
+        };
+
+        if is_import && self.file_chain.contains(&canonical) {
+            let mut chain: Vec<String> = self
+                .file_chain
+                .iter()
+                .skip_while(|path| **path != canonical)
+                .map(|path| path.to_string_lossy().to_string())
+                .collect();
+            chain.push(canonical.to_string_lossy().to_string());
+            self.error_custom(format!("import cycle: {}", chain.join(" -> ")).as_str());
+        }
+
+        let mut p = Parser::new(
+            symbol_analysis(&buf).unwrap(),
+            self.prog_cache,
+            &new_directory,
+        );
+        p.file_chain = [self.file_chain.clone(), vec![canonical.clone()]].concat();
+        let prog = Rc::new(p.run().clone());
+        self.prog_cache.insert(canonical, prog.clone());
+        prog
+    }
+
+    fn new_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
     fn parse_stmt_call(&mut self) {
-        let file_name = self.parse_string();
         self.stat.set_type(StatementType::RUN);
+        self.stat.id = self.new_id();
+        self.stat.persistent = self.accept(TokenType::PERSISTENT);
+        self.parse_call_target();
+        self.parse_call_arguments();
+    }
 
-        let x = self.prog_cache.get(&file_name);
-
-        if matches!(x, None) {
-            let mut new_directory = self.directory.clone();
-            PathBuf::push(&mut new_directory, &file_name);
-            let buf = fs::read_to_string(&new_directory).unwrap_or_else(|_| {
-                eprintln!("could not read file: {}", file_name);
-                process::exit(1);
-            });
-
-            let mut p = Parser::new(
-                symbol_analysis(&buf).unwrap(),
-                self.prog_cache,
-                &new_directory,
-                false,
-            );
-            let prog = Box::new(p.run().clone());
-            self.stat.sub_program = Some(prog.clone());
-            self.prog_cache.insert(file_name, prog);
+    // start "file.sq" as g with ..., a generator that runs one iteration each time it is used with next
+    fn parse_stmt_start(&mut self) {
+        self.stat.set_type(StatementType::START);
+        self.parse_call_target();
+        self.expect_word("as");
+        self.stat.var_name = self.expect_writable_identifier();
+        self.parse_call_arguments();
+    }
+
+    // the program after run or start, either a module or a file name
+    fn parse_call_target(&mut self) {
+        if let TokenType::IDENTIFIER(namespace) = self.current_token.token_type.clone() {
+            // run a module loaded with `use`
+            self.next_token();
+            match self.prog.module(&namespace) {
+                Some(module) => self.stat.sub_program = Some(module.clone()),
+                None => self.error_custom(format!("unknown module {}", namespace).as_str()),
+            }
         } else {
-            self.stat.sub_program = Some((x.unwrap()).clone()); // cleaner way to write?
+            let file_name = self.parse_string();
+            let (_, canonical) = self.program_path(&file_name);
+            if self.file_chain.contains(&canonical) {
+                // the file runs itself, directly or through other files. it's still being parsed,
+                // so the statement finds it in the cache when it runs instead
+                self.stat.program_path = Some(canonical);
+            } else {
+                self.stat.sub_program = Some(self.load_program(&file_name, false));
+            }
         }
+    }
 
-        // need to add way to expose variables to other program
+    fn parse_call_arguments(&mut self) {
+        // plain identifiers are shared with the program, anything else is evaluated into a new history
         if self.accept(TokenType::WITH) {
-            let mut shared: Vec<String> = Vec::new();
             loop {
-                let ident: String = self.expect_identifier().unwrap();
-                shared.push(ident);
+                let mut name: Option<String> = None;
+                if self.current_token.equals(TokenType::IDENTIFIER(String::new()))
+                    && self.ahead(1).equals(TokenType::COLON)
+                {
+                    name = self.expect_identifier();
+                    self.expect(TokenType::COLON);
+                } else if self.stat.arg_names.iter().any(|n| n.is_some()) {
+                    self.error_custom("positional arguments must come before named ones");
+                }
+                let arg = if self.accept(TokenType::AMPERSAND) {
+                    let ident = self.expect_writable_identifier().unwrap();
+                    Expression::new(ExpressionType::REFERENCE(ident), None, None)
+                } else {
+                    self.expr()
+                };
+                self.stat.alt_exps.push(arg);
+                self.stat.arg_names.push(name);
 
                 if !self.accept(TokenType::COMMA) {
                     break;
                 }
             }
-            self.stat.var_list = Some(shared);
         }
     }
+
+    fn parse_stmt_use(&mut self) {
+        self.stat.set_type(StatementType::USE);
+        let file_name = self.parse_string();
+        self.stat.sub_program = Some(self.load_program(&file_name, true));
+        self.expect_word("as");
+        self.stat.var_name = self.expect_writable_identifier();
+    }
 }
@@ -53,7 +53,46 @@ pub enum TokenType {
     QUOTE,
     WITH,
     TAKE,
+    LSQUARE,
+    RSQUARE,
+    COLON,
+    HAS,
+    USE,
+    RETURN,
+    YIELD,
+    DOTDOT,
+    EQUALS,
+    AMPERSAND,
+    ALIAS,
+    CONST,
+    PERSISTENT,
+    FINALLY,
+    REQUIRE,
+    EVER,
+    ALWAYS,
+    SINCE,
+    AT,
+    CHANGED,
+    DEFINE,
+    THEN,
+    DERIVE,
+    FORK,
+    TRY,
+    ROLLBACK,
+    ATOMIC,
+    INVARIANT,
+    ASSERT,
 }
+// the words the lexer always reads as keywords, so they can't name a history. words that only
+// mean something in one place, like next or window, are left as identifiers and recognised by
+// the parser where they are used
+pub const RESERVED_WORDS: [&str; 37] = [
+    "true", "false", "print", "tprint", "prev", "begin", "reveal", "expect", "if", "else", "elif",
+    "and", "or", "not", "run", "with", "take", "has", "use", "return", "yield", "const", "persistent",
+    "finally", "require", "ever", "always", "since", "changed", "then", "derive", "fork", "try",
+    "rollback", "atomic", "invariant", "assert",
+];
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
@@ -119,6 +158,26 @@ impl FromStr for TokenType {
             "RUN" => Ok(TokenType::RUN),
             "WITH" => Ok(TokenType::WITH),
             "TAKE" => Ok(TokenType::TAKE),
+            "HAS" => Ok(TokenType::HAS),
+            "USE" => Ok(TokenType::USE),
+            "RETURN" => Ok(TokenType::RETURN),
+            "YIELD" => Ok(TokenType::YIELD),
+            "CONST" => Ok(TokenType::CONST),
+            "PERSISTENT" => Ok(TokenType::PERSISTENT),
+            "FINALLY" => Ok(TokenType::FINALLY),
+            "REQUIRE" => Ok(TokenType::REQUIRE),
+            "EVER" => Ok(TokenType::EVER),
+            "ALWAYS" => Ok(TokenType::ALWAYS),
+            "SINCE" => Ok(TokenType::SINCE),
+            "CHANGED" => Ok(TokenType::CHANGED),
+            "THEN" => Ok(TokenType::THEN),
+            "DERIVE" => Ok(TokenType::DERIVE),
+            "FORK" => Ok(TokenType::FORK),
+            "TRY" => Ok(TokenType::TRY),
+            "ROLLBACK" => Ok(TokenType::ROLLBACK),
+            "ATOMIC" => Ok(TokenType::ATOMIC),
+            "INVARIANT" => Ok(TokenType::INVARIANT),
+            "ASSERT" => Ok(TokenType::ASSERT),
             _ => return Err(()),
         }
     }
@@ -1,5 +1,9 @@
 
 
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
 use super::expr::Expression;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -9,11 +13,26 @@ pub enum StatementType {
     TPRINT,
     REVEAL,
     ASSIGN,
+    INSERT,
     COPY,
+    ALIAS,
     IF,
     BEGIN,
     EXPECT,
     RUN,
+    USE,
+    RETURN,
+    YIELD,
+    START,
+    FINALLY,
+    REQUIRE,
+    DEFINE,
+    DERIVE,
+    ON,
+    ATOMIC,
+    INVARIANT,
+    ASSERT,
+    TEST,
 }
 
 #[derive(Clone, Debug)]
@@ -21,12 +40,33 @@ pub struct Statement {
     pub statement_type: StatementType,
     pub var_name: Option<String>,
     pub alt_var_name: Option<String>,
-    pub var_list: Option<Vec<String>>,
     pub code_block: Option<Vec<Statement>>,
     pub expr: Option<Box<Expression>>,
     pub alt_code_blocks: Vec<Vec<Statement>>,
     pub alt_exps: Vec<Box<Expression>>,
-    pub sub_program: Option<Box<Program>>,
+    pub arg_names: Vec<Option<String>>, // names given to run arguments (b: x), parallel to alt_exps
+    pub sub_program: Option<Rc<Program>>,
+    pub program_path: Option<PathBuf>, // set instead of sub_program when the program is found in the cache at run time
+    pub persistent: bool, // run persistent: the program keeps its memory between runs
+    pub id: usize,        // unique within a file, used to keep state for the statement
+}
+
+// parsed programs by the canonical path of their file (or std:name), so each file is parsed once
+pub type ProgramCache = HashMap<PathBuf, Rc<Program>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParameterMode {
+    NONE,  // shares the caller's history when given a name
+    REF,   // always shares the caller's history
+    VAL,   // always a copy
+    CONST, // a copy that the program can't write to
+}
+
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub name: String,
+    pub mode: ParameterMode,
+    pub default: Option<Box<Expression>>, // evaluated in the program's memory when not given
 }
 
 #[derive(Clone, Debug)]
@@ -34,28 +74,57 @@ pub struct Program {
     pub name: String,
     pub begin: Option<Statement>,
     pub expect: Vec<Statement>,
+    pub expect_all: bool, // run every expect block that matches instead of only the first
+    pub finally: Option<Statement>,
+    pub require: Vec<Statement>, // checked before each iteration, the program ends if one fails
+    pub recurrences: Vec<Statement>, // name := {seeds} then expr, extended after every iteration
+    pub derived: Vec<Statement>,     // derive name from a, b = expr, recomputed when a source changes
+    pub triggers: Vec<Statement>,    // on change a { ... }
+    pub invariants: Vec<Statement>,  // checked after the body of every iteration
+    pub tests: Vec<Statement>,       // test "name" { ... }, only run by `sequence test`
     pub body: Vec<Statement>,
-    pub parameters: Option<Vec<String>>,
-    pub top_level: bool,
+    pub parameters: Option<Vec<Parameter>>,
+    pub modules: Vec<(String, Rc<Program>)>, // programs loaded with `use`, by namespace
     // TODO: add some new structure to hold taken histories
     // is this done lol
 }
 
 impl Program {
-    pub fn new(name: String, top_level: bool) -> Program {
+    pub fn new(name: String) -> Program {
         Program {
             name: name,
             begin: None,
             expect: Vec::new(),
+            expect_all: false,
+            finally: None,
+            require: Vec::new(),
+            recurrences: Vec::new(),
+            derived: Vec::new(),
+            triggers: Vec::new(),
+            invariants: Vec::new(),
+            tests: Vec::new(),
             body: Vec::new(),
             parameters: None,
-            top_level: top_level,
+            modules: Vec::new(),
         }
     }
 
     pub fn add(&mut self, s: Statement) {
         self.body.push(s);
     }
+
+    // finds a module by namespace, following nested modules for names like lib.other
+    pub fn module(&self, namespace: &str) -> Option<&Rc<Program>> {
+        let (first, rest) = match namespace.split_once('.') {
+            Some((first, rest)) => (first, Some(rest)),
+            None => (namespace, None),
+        };
+        let module = &self.modules.iter().find(|(name, _)| name == first)?.1;
+        match rest {
+            Some(rest) => module.module(rest),
+            None => Some(module),
+        }
+    }
 }
 
 impl Statement {
@@ -64,12 +133,15 @@ impl Statement {
             statement_type: StatementType::NONE,
             var_name: None,
             alt_var_name: None,
-            var_list: None,
             code_block: None,
             expr: None,
             alt_code_blocks: Vec::new(),
             alt_exps: Vec::new(),
+            arg_names: Vec::new(),
             sub_program: None,
+            program_path: None,
+            persistent: false,
+            id: 0,
         }
     }
     pub fn set_type(&mut self, t: StatementType) {
@@ -78,9 +150,14 @@ impl Statement {
     pub fn reset(&mut self) {
         self.statement_type = StatementType::NONE;
         self.var_name = None;
+        self.alt_var_name = None;
         self.expr = None;
         self.code_block = None;
         self.alt_code_blocks = Vec::new();
         self.alt_exps = Vec::new();
+        self.arg_names = Vec::new();
+        self.program_path = None;
+        self.persistent = false;
+        self.id = 0;
     }
 }
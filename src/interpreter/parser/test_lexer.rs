@@ -132,3 +132,61 @@ fn test_comment() {
     let tokens = lexer::symbol_analysis(s).unwrap();
     expect_tokens(tokens, vec![]); // comments should be ignored by lexer
 }
+
+#[test]
+fn test_map_symbols() {
+    let s = "m[\"a\"] : has";
+    let tokens = lexer::symbol_analysis(s).unwrap();
+    expect_tokens(
+        tokens,
+        vec![
+            TokenType::IDENTIFIER(String::from("m")),
+            TokenType::LSQUARE,
+            TokenType::STRING(String::from("a")),
+            TokenType::RSQUARE,
+            TokenType::COLON,
+            TokenType::HAS,
+        ],
+    );
+}
+
+#[test]
+fn test_keyword_prefix() {
+    let s = "printer iffy";
+    let tokens = lexer::symbol_analysis(s).unwrap();
+    expect_tokens(
+        tokens,
+        vec![
+            TokenType::IDENTIFIER(String::from("printer")),
+            TokenType::IDENTIFIER(String::from("iffy")),
+        ],
+    );
+}
+
+#[test]
+fn test_module_member() {
+    let s = "geo.pi 1.5";
+    let tokens = lexer::symbol_analysis(s).unwrap();
+    expect_tokens(
+        tokens,
+        vec![
+            TokenType::IDENTIFIER(String::from("geo.pi")),
+            TokenType::FLOAT(1.5),
+        ],
+    );
+}
+
+#[test]
+fn test_range() {
+    let s = "0..3 1.5";
+    let tokens = lexer::symbol_analysis(s).unwrap();
+    expect_tokens(
+        tokens,
+        vec![
+            TokenType::INTEGER(0),
+            TokenType::DOTDOT,
+            TokenType::INTEGER(3),
+            TokenType::FLOAT(1.5),
+        ],
+    );
+}
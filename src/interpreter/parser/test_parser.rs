@@ -1,9 +1,13 @@
 #[cfg(test)]
-use std::{collections::HashMap, path::PathBuf};
+use std::path::PathBuf;
 #[cfg(test)]
 use crate::interpreter::parser::expr::{Expression, ExpressionType};
 #[cfg(test)]
-use super::{lexer, parse::{Parser}, statement::{Program, StatementType}};
+use std::rc::Rc;
+#[cfg(test)]
+use crate::interpreter::executor::preload;
+#[cfg(test)]
+use super::{lexer, parse::{Parser}, statement::{ParameterMode, Program, ProgramCache, StatementType}};
 
 #[cfg(test)]
 fn expect_parse(s: &str, body: Vec<StatementType>) {
@@ -17,9 +21,9 @@ fn expect_parse(s: &str, body: Vec<StatementType>) {
 
 #[cfg(test)]
 fn run_parser(s: &str) ->  Program {
-    let mut prog_cache: HashMap<String, Box<Program>> = HashMap::new();
+    let mut prog_cache: ProgramCache = ProgramCache::new();
     let test_path: PathBuf = PathBuf::new();
-    let mut p = Parser::new(lexer::symbol_analysis(&s).unwrap(), &mut prog_cache, &test_path, true);
+    let mut p = Parser::new(lexer::symbol_analysis(&s).unwrap(), &mut prog_cache, &test_path);
     return p.run().clone();
 }
 
@@ -50,4 +54,345 @@ fn test_simple_add() {
     let rhs = Expression::new(ExpressionType::INTEGER(2), None, None);
     let expr = Expression::new(ExpressionType::ADD, Some(lhs), Some(rhs));
     assert_eq!(*stat.expr.unwrap(), *expr);
-}
\ No newline at end of file
+}
+#[test]
+fn test_map_literal() {
+    let s = "m <- {\"a\": 1, 2: 3}\nm[\"b\"] <- m[\"a\"]";
+    let prog = run_parser(s);
+
+    assert_eq!(prog.body[0].statement_type, StatementType::ASSIGN);
+    let map = prog.body[0].expr.clone().unwrap();
+    assert_eq!(map.exp_type, ExpressionType::MAP);
+    assert_eq!(map.args.len(), 4);
+
+    assert_eq!(prog.body[1].statement_type, StatementType::INSERT);
+    assert_eq!(prog.body[1].expr.clone().unwrap().exp_type, ExpressionType::INDEX);
+}
+
+#[test]
+fn test_use() {
+    let s = "use \"examples/geometry.sq\" as geo\nprint(geo.pi)";
+    let prog = run_parser(s);
+
+    assert_eq!(prog.modules.len(), 1);
+    assert_eq!(prog.modules[0].0, String::from("geo"));
+    assert!(prog.module("geo").unwrap().begin.is_some());
+    expect_parse(s, vec![StatementType::PRINT]);
+}
+
+#[test]
+fn test_run_expression() {
+    let s = "y <- 1 + run \"examples/square.sq\" with x\nexpect true {\nreturn y\n}";
+    let prog = run_parser(s);
+
+    let expr = prog.body[0].expr.clone().unwrap();
+    let call = expr.rhs.unwrap();
+    match call.exp_type {
+        ExpressionType::RUN(stat) => {
+            assert_eq!(stat.statement_type, StatementType::RUN);
+            assert_eq!(stat.alt_exps.len(), 1);
+            assert_eq!(stat.alt_exps[0].exp_type, ExpressionType::IDENTIFIER(String::new()));
+        }
+        _ => panic!("expected a run expression, got {:?}", call.exp_type),
+    }
+    assert_eq!(prog.expect[0].code_block.clone().unwrap()[0].statement_type, StatementType::RETURN);
+}
+
+#[test]
+fn test_run_arguments() {
+    let s = "run \"std:max\" with {1, 2, 3}, a + 1, $b::0..3, 1..2::$b";
+    let prog = run_parser(s);
+
+    let args = prog.body[0].alt_exps.clone();
+    assert_eq!(args.len(), 4);
+    assert_eq!(args[0].exp_type, ExpressionType::HISTORY);
+    assert_eq!(args[0].args.len(), 3);
+    assert_eq!(args[1].exp_type, ExpressionType::ADD);
+    assert!(matches!(args[2].exp_type, ExpressionType::SLICE(true)));
+    assert!(matches!(args[3].exp_type, ExpressionType::SLICE(false)));
+    assert_eq!(args[3].var_name, Some(String::from("b")));
+}
+
+#[test]
+fn test_take_defaults() {
+    let s = "take a, b = {0}, verbose = false\nrun \"std:max\" with a, max: b";
+    let prog = run_parser(s);
+
+    let params = prog.parameters.unwrap();
+    assert_eq!(params.len(), 3);
+    assert_eq!(params[0].name, String::from("a"));
+    assert!(params[0].default.is_none());
+    assert_eq!(params[1].default.clone().unwrap().exp_type, ExpressionType::HISTORY);
+    assert_eq!(params[2].default.clone().unwrap().exp_type, ExpressionType::BOOL(false));
+
+    assert_eq!(prog.body[0].arg_names, vec![None, Some(String::from("max"))]);
+}
+
+#[test]
+fn test_parameter_modes() {
+    let s = "take ref a, val b, const c, d\nrun \"examples/swap.sq\" with &a, b";
+    let prog = run_parser(s);
+
+    let modes: Vec<ParameterMode> = prog.parameters.unwrap().iter().map(|p| p.mode).collect();
+    assert_eq!(modes, vec![ParameterMode::REF, ParameterMode::VAL, ParameterMode::CONST, ParameterMode::NONE]);
+
+    let args = prog.body[0].alt_exps.clone();
+    assert_eq!(args[0].exp_type, ExpressionType::REFERENCE(String::new()));
+    assert_eq!(args[1].exp_type, ExpressionType::IDENTIFIER(String::new()));
+}
+
+#[test]
+fn test_copy_and_alias() {
+    let s = "b =: a\nc =& a";
+    expect_parse(s, vec![StatementType::COPY, StatementType::ALIAS]);
+}
+
+#[test]
+fn test_run_persistent() {
+    let s = "run persistent \"std:max\" with a, b\nrun \"std:max\" with a, b";
+    let prog = run_parser(s);
+
+    assert!(prog.body[0].persistent);
+    assert!(!prog.body[1].persistent);
+    assert_ne!(prog.body[0].id, prog.body[1].id);
+}
+
+#[test]
+fn test_generator() {
+    let s = "start \"std:max\" as g with a, b\nx <- next g\nyield done g";
+    let prog = run_parser(s);
+
+    assert_eq!(prog.body[0].statement_type, StatementType::START);
+    assert_eq!(prog.body[0].var_name, Some(String::from("g")));
+    assert_eq!(prog.body[0].alt_exps.len(), 2);
+    assert!(matches!(prog.body[1].expr.clone().unwrap().exp_type, ExpressionType::NEXT(_)));
+    assert_eq!(prog.body[2].statement_type, StatementType::YIELD);
+    assert!(matches!(prog.body[2].expr.clone().unwrap().exp_type, ExpressionType::DONE(_)));
+}
+
+#[test]
+fn test_yield_in_expect_returns() {
+    let prog = run_parser("take x\nexpect true {\n    yield x * x\n}\nyield x");
+
+    assert_eq!(prog.expect[0].code_block.as_ref().unwrap()[0].statement_type, StatementType::RETURN);
+    assert_eq!(prog.body[0].statement_type, StatementType::YIELD);
+}
+
+#[test]
+fn test_recursive_run() {
+    let mut prog_cache: ProgramCache = ProgramCache::new();
+    let path = PathBuf::from("examples/factorial.sq");
+    let buf = std::fs::read_to_string(&path).unwrap();
+    let mut p = Parser::new(lexer::symbol_analysis(&buf).unwrap(), &mut prog_cache, &path);
+    let prog = p.run().clone();
+
+    // the file runs itself, so the run is left to be looked up when it executes
+    let call = match &prog.expect[1].code_block.as_ref().unwrap()[0].expr.as_ref().unwrap().rhs {
+        Some(rhs) => match &rhs.exp_type {
+            ExpressionType::RUN(call) => call.clone(),
+            _ => panic!("expected a run expression"),
+        },
+        None => panic!("expected a run expression"),
+    };
+    assert!(call.sub_program.is_none());
+    assert_eq!(call.program_path, Some(std::fs::canonicalize(&path).unwrap()));
+}
+
+#[test]
+fn test_preload() {
+    let mut prog_cache: ProgramCache = ProgramCache::new();
+    let lib = preload(&mut prog_cache, &PathBuf::from("virtual/lib.sq"), "take a\nprint(a)\n");
+
+    // the file doesn't exist, so it's only found because it was preloaded
+    let main = preload(&mut prog_cache, &PathBuf::from("virtual/main.sq"), "run \"lib.sq\" with 1\nrun \"lib.sq\" with 2\n");
+    assert!(Rc::ptr_eq(main.body[0].sub_program.as_ref().unwrap(), &lib));
+    assert!(Rc::ptr_eq(main.body[1].sub_program.as_ref().unwrap(), &lib));
+    assert_eq!(prog_cache.len(), 2);
+}
+
+#[test]
+fn test_finally_and_require() {
+    let s = "expect all\nrequire a < 3\nexpect a > 1 {\n}\nfinally {\nprint(a)\n}\na <- a + 1";
+    let prog = run_parser(s);
+
+    assert!(prog.expect_all);
+    assert_eq!(prog.require.len(), 1);
+    assert_eq!(prog.expect.len(), 1);
+    assert_eq!(prog.finally.unwrap().code_block.unwrap().len(), 1);
+    assert_eq!(prog.body.len(), 1);
+}
+
+#[test]
+fn test_temporal_conditions() {
+    let s = "expect ever a > 1 since b for 3 iterations {\n}\nif always a {\n}";
+    let prog = run_parser(s);
+
+    let cond = prog.expect[0].expr.clone().unwrap();
+    assert!(matches!(cond.exp_type, ExpressionType::SINCE(_)));
+    assert!(matches!(cond.lhs.unwrap().exp_type, ExpressionType::EVER(_)));
+    let rhs = cond.rhs.unwrap();
+    assert!(matches!(rhs.exp_type, ExpressionType::FOR(_)));
+    assert_eq!(rhs.rhs.unwrap().exp_type, ExpressionType::INTEGER(3));
+    assert!(matches!(prog.body[0].expr.clone().unwrap().exp_type, ExpressionType::ALWAYS(_)));
+}
+
+#[test]
+fn test_meta_values() {
+    let s = "print(@iter, @depth, @program)";
+    let prog = run_parser(s);
+
+    let print = &prog.body[0];
+    assert_eq!(print.expr.clone().unwrap().exp_type, ExpressionType::META(String::from("iter")));
+    assert_eq!(print.alt_exps[0].exp_type, ExpressionType::META(String::from("depth")));
+    assert_eq!(print.alt_exps[1].exp_type, ExpressionType::META(String::from("program")));
+}
+
+#[test]
+fn test_at_iteration() {
+    let s = "x <- at 2 { a + b } + 1";
+    let prog = run_parser(s);
+
+    let exp = prog.body[0].expr.clone().unwrap();
+    assert_eq!(exp.exp_type, ExpressionType::ADD);
+    let at = exp.lhs.unwrap();
+    assert_eq!(at.exp_type, ExpressionType::AT);
+    assert_eq!(at.lhs.unwrap().exp_type, ExpressionType::INTEGER(2));
+    assert_eq!(at.rhs.unwrap().exp_type, ExpressionType::ADD);
+}
+
+#[test]
+fn test_prev_steps() {
+    let s = "x <- prev 3 a default 0\ny <- prev a or b\nz <- delta a + first c";
+    let prog = run_parser(s);
+
+    let x = prog.body[0].expr.clone().unwrap();
+    assert!(matches!(&x.exp_type, ExpressionType::PREV(name) if name == "a"));
+    assert_eq!(x.lhs.unwrap().exp_type, ExpressionType::INTEGER(3));
+    assert_eq!(x.rhs.unwrap().exp_type, ExpressionType::INTEGER(0));
+
+    // or after prev is a logical or
+    let y = prog.body[1].expr.clone().unwrap();
+    assert_eq!(y.exp_type, ExpressionType::OR);
+    let prev = y.lhs.unwrap();
+    assert!(matches!(&prev.exp_type, ExpressionType::PREV(name) if name == "a"));
+    assert!(prev.rhs.is_none());
+    assert!(matches!(&y.rhs.unwrap().exp_type, ExpressionType::IDENTIFIER(name) if name == "b"));
+
+    let z = prog.body[2].expr.clone().unwrap();
+    assert_eq!(z.exp_type, ExpressionType::ADD);
+    assert!(matches!(&z.lhs.unwrap().exp_type, ExpressionType::DELTA(name) if name == "a"));
+    assert!(matches!(&z.rhs.unwrap().exp_type, ExpressionType::FIRST(name) if name == "c"));
+}
+
+#[test]
+fn test_window() {
+    let s = "x <- mean(window a 5)\ny <- sum(window b (n + 1))";
+    let prog = run_parser(s);
+
+    let x = prog.body[0].expr.clone().unwrap();
+    let window = x.args[0].clone();
+    assert!(matches!(window.exp_type, ExpressionType::WINDOW(_)));
+    assert_eq!(window.var_name, Some(String::from("a")));
+    assert_eq!(window.lhs.unwrap().exp_type, ExpressionType::INTEGER(5));
+
+    // every window gets its own id, so each keeps its own running totals
+    let y = prog.body[1].expr.clone().unwrap();
+    let other = y.args[0].clone();
+    assert_eq!(other.var_name, Some(String::from("b")));
+    assert_eq!(other.lhs.unwrap().exp_type, ExpressionType::ADD);
+    match (window.exp_type, other.exp_type) {
+        (ExpressionType::WINDOW(a), ExpressionType::WINDOW(b)) => assert_ne!(a, b),
+        _ => panic!("expected two windows"),
+    }
+}
+
+#[test]
+fn test_recurrence() {
+    let s = "fib := {0, 1} then prev fib + fib\nprint(fib)";
+    let prog = run_parser(s);
+
+    // definitions are kept apart from the body, like begin and expect
+    assert_eq!(prog.body.len(), 1);
+    assert_eq!(prog.recurrences.len(), 1);
+    let fib = &prog.recurrences[0];
+    assert_eq!(fib.statement_type, StatementType::DEFINE);
+    assert_eq!(fib.var_name, Some(String::from("fib")));
+    assert_eq!(fib.alt_exps.len(), 2);
+    assert_eq!(fib.expr.clone().unwrap().exp_type, ExpressionType::ADD);
+}
+
+#[test]
+fn test_derive_and_on_change() {
+    let s = "derive total from a, b = a + b\non change total {\n    print(total)\n}";
+    let prog = run_parser(s);
+
+    assert!(prog.body.is_empty());
+    let total = &prog.derived[0];
+    assert_eq!(total.var_name, Some(String::from("total")));
+    assert_eq!(total.arg_names, vec![Some(String::from("a")), Some(String::from("b"))]);
+    assert_eq!(total.expr.clone().unwrap().exp_type, ExpressionType::ADD);
+
+    let trigger = &prog.triggers[0];
+    assert_eq!(trigger.statement_type, StatementType::ON);
+    assert_eq!(trigger.var_name, Some(String::from("total")));
+    assert_eq!(trigger.code_block.as_ref().unwrap().len(), 1);
+}
+
+#[test]
+fn test_fork() {
+    let s = "b <- fork a at i + 1";
+    let prog = run_parser(s);
+
+    let fork = prog.body[0].expr.clone().unwrap();
+    assert_eq!(fork.exp_type, ExpressionType::FORK);
+    assert_eq!(fork.var_name, Some(String::from("a")));
+    assert_eq!(fork.lhs.unwrap().exp_type, ExpressionType::ADD);
+}
+
+#[test]
+fn test_try_and_atomic() {
+    let s = "try {\n    a <- 1\n} rollback if a > 3\natomic {\n    b <- 2\n}";
+    let prog = run_parser(s);
+
+    let tried = &prog.body[0];
+    assert_eq!(tried.statement_type, StatementType::ATOMIC);
+    assert_eq!(tried.code_block.as_ref().unwrap().len(), 1);
+    assert_eq!(tried.expr.clone().unwrap().exp_type, ExpressionType::GTH);
+
+    // atomic only rolls back on an error
+    let atomic = &prog.body[1];
+    assert_eq!(atomic.statement_type, StatementType::ATOMIC);
+    assert!(atomic.expr.is_none());
+}
+
+#[test]
+fn test_invariant() {
+    let s = "invariant i <= #a and prev 2 b default 0 < 10 for 3 iterations\ni <- i + 1";
+    let prog = run_parser(s);
+
+    assert_eq!(prog.body.len(), 1);
+    let invariant = prog.invariants[0].expr.clone().unwrap();
+    assert_eq!(
+        invariant.to_string(),
+        "((i <= #a) and (prev 2 b default 0 < 10)) for 3 iterations"
+    );
+    assert_eq!(invariant.histories(), vec!["i", "a", "b"]);
+}
+
+#[test]
+fn test_assert_and_test() {
+    let s = "test \"doubles\" {\n    a <- 2\n    assert a * 2 == 4, \"twice two\"\n    assert a > 1\n}\na <- 1";
+    let prog = run_parser(s);
+
+    assert_eq!(prog.body.len(), 1);
+    assert_eq!(prog.tests.len(), 1);
+    let test = &prog.tests[0];
+    assert_eq!(test.var_name.as_deref(), Some("doubles"));
+
+    let block = test.code_block.as_ref().unwrap();
+    assert_eq!(block.len(), 3);
+    assert_eq!(block[1].statement_type, StatementType::ASSERT);
+    assert_eq!(block[1].expr.as_ref().unwrap().to_string(), "(a * 2) == 4");
+    assert_eq!(block[1].alt_var_name.as_deref(), Some("twice two"));
+    assert_eq!(block[2].alt_var_name, None);
+}
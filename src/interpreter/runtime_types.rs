@@ -1,13 +1,69 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::rc::Rc;
 
+use super::parser::expr::{Expression, ExpressionType};
+use super::parser::statement::{Program, ProgramCache};
+use super::window::Window;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum VariableType {
     FLOAT(f64),
     INTEGER(i64),
     BOOL(bool),
     STRING(String),
+    MAP(Rc<BTreeMap<MapKey, VariableType>>),
+}
+
+// only integers and strings can be used as map keys
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MapKey {
+    INTEGER(i64),
+    STRING(String),
+}
+
+impl MapKey {
+    pub fn to_variable(&self) -> VariableType {
+        match self {
+            MapKey::INTEGER(x) => VariableType::INTEGER(*x),
+            MapKey::STRING(x) => VariableType::STRING(x.clone()),
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapKey::INTEGER(x) => write!(f, "{}", x),
+            MapKey::STRING(x) => write!(f, "\"{}\"", x),
+        }
+    }
+}
+
+impl fmt::Display for VariableType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VariableType::FLOAT(x) => write!(f, "{}", x),
+            VariableType::INTEGER(x) => write!(f, "{}", x),
+            VariableType::BOOL(x) => write!(f, "{}", x),
+            VariableType::STRING(x) => write!(f, "{}", x),
+            VariableType::MAP(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    match value {
+                        VariableType::STRING(x) => write!(f, "{}: \"{}\"", key, x)?,
+                        _ => write!(f, "{}: {}", key, value)?,
+                    }
+                }
+                write!(f, "}}")
+            }
+        }
+    }
 }
 
 impl VariableType {
@@ -17,6 +73,28 @@ impl VariableType {
             Self::INTEGER(x) => *x >= 1,
             Self::BOOL(x) => *x,
             Self::STRING(x) => *x != "".to_string(),
+            Self::MAP(x) => !x.is_empty(),
+        }
+    }
+
+    pub fn as_key(&self) -> MapKey {
+        match self {
+            Self::INTEGER(x) => MapKey::INTEGER(*x),
+            Self::STRING(x) => MapKey::STRING(x.clone()),
+            _ => runtime_error!("map keys must be integers or strings, got {}", self),
+        }
+    }
+
+    // returns a new map with key set to value, the original is left alone so older
+    // entries of a history keep their contents
+    pub fn with_key(&self, key: MapKey, value: VariableType) -> Self {
+        match self {
+            Self::MAP(x) => {
+                let mut map = (**x).clone();
+                map.insert(key, value);
+                Self::MAP(Rc::new(map))
+            }
+            _ => runtime_error!("cannot set key {} on {}, it is not a map", key, self),
         }
     }
 
@@ -38,6 +116,7 @@ impl VariableType {
             Self::BOOL(x) => *self = Self::INTEGER(*x as i64),
             Self::FLOAT(x) => *self = Self::INTEGER(*x as i64),
             Self::STRING(_x) => *self = Self::INTEGER(0), // neeed to change later
+            Self::MAP(_x) => *self = Self::INTEGER(0),
             Self::INTEGER(_x) => {}
         }
         self.clone()
@@ -67,67 +146,490 @@ impl VariableType {
 
 #[derive(Clone, Debug)]
 pub struct History {
+    // the first values, when they are shared with histories forked from this one or the one this
+    // was forked from: the segments holding them, in order, and how many of their values are this
+    // history's. histories forked from each other share the list until one of them adds to it
+    segments: Rc<Vec<Rc<Segment>>>,
+    shared_len: usize,
+    // copies of a history share their values until one of them is appended to
+    items: Rc<Vec<VariableType>>,
+    stamps: Rc<Vec<Stamp>>, // when each value was appended, parallel to items
+    visible: usize, // how many of items are this history's, the rest were appended to a copy it is a view of
+    // a copy keeps the values it was made from, and with them their stamps. the first copied
+    // values are the ones it was made with, which count as appended when it was made instead
+    copied: usize,
+    copy_stamp: Stamp,
+}
+
+// values frozen by a fork. they never change, so any number of histories can share them
+#[derive(Debug)]
+struct Segment {
+    start: usize, // the index of its first value in the histories sharing it
     items: Vec<VariableType>,
+    stamps: Vec<Stamp>,
 }
 
-pub type HistoryCollection = Vec<SharedHistory>;
+// when a value was added to a history. values that didn't come from an assignment
+// (command line parameters, history literals) are stamped with time 0
+#[derive(Clone, Debug, Default)]
+pub struct Stamp {
+    pub iteration: usize, // the iteration of the program that appended it
+    pub program: Rc<str>,
+    pub time: u64, // counts every value appended during the run, across all programs
+}
+
+// how the caller gave an argument, which together with the parameter's mode decides
+// whether the program gets the caller's history or a copy of it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Passing {
+    VALUE,     // an expression, evaluated into a new history
+    NAME,      // a plain history name, shared unless the parameter is val or const
+    REFERENCE, // &name, always shared
+}
+
+// a history passed to a program, bound by position unless it is named
+pub struct Argument {
+    pub name: Option<String>,
+    pub history: SharedHistory,
+    pub passing: Passing,
+}
+
+pub type HistoryCollection = Vec<Argument>;
 pub type SharedHistory = Rc<RefCell<History>>;
 
 impl History {
     pub fn new() -> History { 
         History { 
-            items: vec![],
+            segments: Rc::new(vec![]),
+            shared_len: 0,
+            items: Rc::new(vec![]),
+            stamps: Rc::new(vec![]),
+            visible: 0,
+            copied: 0,
+            copy_stamp: Stamp::default(),
         }
     }
 
     pub fn alloc(_name: String, val: VariableType) -> SharedHistory {
-        Rc::new(RefCell::new(History {
-            items: vec![val],
-        }))
+        let mut history = History::new();
+        history.add(val);
+        Rc::new(RefCell::new(history))
     }
     pub fn add(&mut self, val: VariableType) {
-        self.items.push(val);
+        self.add_stamped(val, Stamp::default());
+    }
+
+    pub fn add_stamped(&mut self, val: VariableType, stamp: Stamp) {
+        self.own_items();
+        Rc::make_mut(&mut self.items).push(val);
+        Rc::make_mut(&mut self.stamps).push(stamp);
+        self.visible += 1;
+    }
+
+    // drops the values after the ones this history sees, copying the rest if they are shared
+    fn own_items(&mut self) {
+        if self.items.len() > self.visible {
+            self.items = Rc::new(self.items[..self.visible].to_vec());
+            self.stamps = Rc::new(self.stamps[..self.visible].to_vec());
+        }
+    }
+
+    // takes back the last value appended. if a fork has since moved it into a shared segment
+    // it stays there, this history just stops seeing it
+    pub fn pop(&mut self) {
+        if self.visible > 0 {
+            self.visible -= 1;
+            self.own_items();
+        } else if self.shared_len > 0 {
+            self.shared_len -= 1;
+        }
+        self.copied = self.copied.min(self.len());
+    }
+
+    // the segment holding the value at index, a shared one, and where it is in that segment.
+    // a later segment can start inside an earlier one this history stopped seeing, so the last
+    // one starting at or before index is the one
+    fn find(&self, index: usize) -> (&Segment, usize) {
+        let position = self.segments.partition_point(|segment| segment.start <= index) - 1;
+        let segment = &self.segments[position];
+        (segment, index - segment.start)
+    }
+
+    pub fn get_stamp(&self, index: usize) -> &Stamp {
+        if index < self.copied {
+            return &self.copy_stamp;
+        }
+        if index < self.shared_len {
+            let (segment, index) = self.find(index);
+            return &segment.stamps[index];
+        }
+        &self.stamps[index - self.shared_len]
     }
 
     pub fn len(&self) -> usize {
-        self.items.len()
+        self.shared_len + self.visible
     }
 
     pub fn get_past(&self, index: usize) -> VariableType {
-        self.items[index].clone()
+        if index < self.shared_len {
+            let (segment, index) = self.find(index);
+            return segment.items[index].clone();
+        }
+        self.items[index - self.shared_len].clone()
+    }
+
+    // a new history holding the values from start up to (not including) end
+    pub fn slice(&self, start: usize, end: usize) -> History {
+        let mut history = History::new();
+        for i in start..end {
+            history.add_stamped(self.get_past(i), self.get_stamp(i).clone());
+        }
+        history
+    }
+
+    // the values that had been appended by the given time. stamps only grow along a history,
+    // so they are found with a binary search, and the history returned shares them with this one
+    pub fn as_of(&self, time: u64) -> History {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.get_stamp(middle).time <= time {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        self.prefix(low)
+    }
+
+    // a history seeing only the first len values of this one
+    fn prefix(&self, len: usize) -> History {
+        let copied = self.copied.min(len);
+        if len > self.shared_len {
+            return History { visible: len - self.shared_len, copied, ..self.clone() };
+        }
+        History {
+            segments: self.segments.clone(),
+            shared_len: len,
+            copied,
+            copy_stamp: self.copy_stamp.clone(),
+            ..History::new()
+        }
+    }
+
+    // a copy of the values, all stamped as appended now. like a fork it shares them with this
+    // history, so neither has to copy them when the other is appended to
+    pub fn copy(&mut self, stamp: Stamp) -> History {
+        let mut copy = match self.len() {
+            0 => History::new(),
+            len => self.fork(len - 1),
+        };
+        copy.copied = copy.len();
+        copy.copy_stamp = stamp;
+        copy
+    }
+
+    // a new history sharing the values up to and including index with this one. values of this
+    // one up to there that aren't shared yet are moved into a segment both of them point to, so
+    // only the values after index are copied
+    pub fn fork(&mut self, index: usize) -> History {
+        let len = index + 1;
+        let shared_len = self.shared_len;
+        if len > shared_len {
+            self.own_items();
+            let mut items = std::mem::take(Rc::make_mut(&mut self.items));
+            let mut stamps = std::mem::take(Rc::make_mut(&mut self.stamps));
+            self.items = Rc::new(items.split_off(len - shared_len));
+            self.stamps = Rc::new(stamps.split_off(len - shared_len));
+            self.visible = self.items.len();
+            let segment = Segment {
+                start: shared_len,
+                items,
+                stamps,
+            };
+            // segments starting after the values this history sees aren't its own any more
+            let segments = Rc::make_mut(&mut self.segments);
+            segments.truncate(segments.partition_point(|segment| segment.start < shared_len));
+            segments.push(Rc::new(segment));
+            self.shared_len = len;
+        }
+        History {
+            segments: self.segments.clone(),
+            shared_len: len,
+            copied: self.copied.min(len),
+            copy_stamp: self.copy_stamp.clone(),
+            ..History::new()
+        }
+    }
+
+    // how many values at the start this history shares with other through forking. two lists
+    // of segments are the same up to where the histories went apart, so that is searched for
+    pub fn shared_with(&self, other: &History) -> usize {
+        let (mine, theirs) = (&self.segments, &other.segments);
+        let (mut common, mut high) = (0, mine.len().min(theirs.len()));
+        while common < high {
+            let middle = (common + high) / 2;
+            if Rc::ptr_eq(&mine[middle], &theirs[middle]) {
+                common = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        if common == 0 {
+            return 0;
+        }
+        // the values from where either has a segment of its own on are different
+        let apart = [mine.get(common), theirs.get(common)]
+            .into_iter()
+            .flatten()
+            .map(|segment| segment.start)
+            .min()
+            .unwrap_or(usize::MAX);
+        self.shared_len.min(other.shared_len).min(apart)
     }
 }
 
 pub struct Memory {
     pub cells: HashMap<String, SharedHistory>,
+    pub result: Option<VariableType>, // set by return, ends the block it is in
+    pub yielded: Option<VariableType>, // set by yield, the value of the current iteration
+    pub persistent: HashMap<usize, Memory>, // memory of programs run persistent, by statement id
+    pub generators: HashMap<String, Generator>,
+    pub constants: Vec<String>, // const parameters, which are only ever passed on as copies
+    pub programs: Rc<RefCell<ProgramCache>>, // shared by every program in the run
+    pub call_chain: Vec<Rc<str>>, // the programs that ran this one, ending with it
+    pub top_level: bool, // the program was given on the command line, so tprint prints
+    pub iteration: usize, // how many times the body has run
+    pub clock: Rc<Cell<u64>>, // the time of the last value appended, shared by every program in the run
+    pub iteration_ends: Rc<Vec<u64>>, // the time at the end of each iteration, shared with snapshots
+    pub temporal: HashMap<usize, Temporal>, // state of temporal conditions, by id
+    pub windows: HashMap<usize, Window>, // statistics of window expressions, by id
+    pub recurrences: Vec<(String, Box<Expression>)>, // histories defined with :=, in the order they were written
+    pub dependents: HashMap<String, Vec<Dependent>>, // derive and on change statements, by the history they watch
+    pub changed: Vec<String>,  // watched histories that got a value, waiting for their dependents to run
+    pub reacting: Vec<String>, // histories whose dependents are running, to catch ones that change each other forever
+    pub journal: Vec<Vec<Change>>, // changes made in each try or atomic block being run, innermost last
+}
+
+// an error in the program being run, as opposed to a bug in the interpreter. it unwinds like a
+// panic, so try and atomic blocks can catch it while a bug still ends the run
+pub struct RuntimeError(pub String);
+
+// an invariant or assert that doesn't hold. rolling back can't make it hold, so try and atomic
+// blocks don't catch it, only the test runner does
+pub struct Violation(pub String);
+
+// the message of a runtime error or violation, none for any other panic
+pub fn error_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(error) = payload.downcast_ref::<RuntimeError>() {
+        return Some(&error.0);
+    }
+    payload.downcast_ref::<Violation>().map(|violation| violation.0.as_str())
+}
+
+// a change to the histories made inside a try or atomic block, kept so it can be undone
+pub enum Change {
+    Appended(SharedHistory),               // a value was appended to it
+    Replaced(SharedHistory, History),      // its values were replaced, these are the old ones
+    Bound(String, Option<SharedHistory>),  // the name was bound to a history, this is what it was bound to before
+}
+
+// a derive or on change statement watching a history, by its index in the derived or triggers
+// of the program using the memory
+#[derive(Clone, Copy, Debug)]
+pub enum Dependent {
+    Derived(usize),
+    Trigger(usize),
+}
+
+// what a temporal condition remembers of the iterations that have finished
+#[derive(Default, Clone, Copy)]
+pub struct Temporal {
+    pub checked: usize, // how many iterations have been folded in
+    pub held: bool,     // ever: true in one of them, always: in all of them, since: at the end of the last
+    pub streak: usize,  // for: how many of the last ones in a row it was true in
+}
+
+impl Temporal {
+    // the state once one more iteration, in which the condition was cond, is folded in.
+    // start is the right side of since
+    pub fn next(&self, exp_type: &ExpressionType, cond: bool, start: bool) -> Temporal {
+        let mut next = *self;
+        match exp_type {
+            ExpressionType::EVER(_) => next.held = self.held || cond,
+            ExpressionType::ALWAYS(_) => next.held = self.held && cond,
+            ExpressionType::SINCE(_) => next.held = start || (self.held && cond),
+            ExpressionType::FOR(_) => next.streak = if cond { self.streak + 1 } else { 0 },
+            _ => unreachable!(),
+        }
+        next
+    }
+}
+
+// a program started with start, which runs one iteration each time next is used on it
+pub struct Generator {
+    pub program: Rc<Program>,
+    pub memory: Memory,
+    pub done: bool, // one of its expect blocks fired
+    pub last: Option<VariableType>, // the last value it gave
+    pub shared: Vec<SharedHistory>, // arguments shared with the program that started it
 }
 
 impl Memory {
     pub fn new() -> Memory {
-        Memory { cells: HashMap::new() }
+        Memory {
+            cells: HashMap::new(),
+            result: None,
+            yielded: None,
+            persistent: HashMap::new(),
+            generators: HashMap::new(),
+            constants: Vec::new(),
+            programs: Rc::new(RefCell::new(ProgramCache::new())),
+            call_chain: Vec::new(),
+            top_level: false,
+            iteration: 0,
+            clock: Rc::new(Cell::new(0)),
+            iteration_ends: Rc::new(Vec::new()),
+            temporal: HashMap::new(),
+            windows: HashMap::new(),
+            recurrences: Vec::new(),
+            dependents: HashMap::new(),
+            changed: Vec::new(),
+            reacting: Vec::new(),
+            journal: Vec::new(),
+        }
+    }
+
+    // memory for a program run by the one using this memory
+    pub fn child(&self, name: &str) -> Memory {
+        let mut memory = Memory::new();
+        memory.programs = self.programs.clone();
+        memory.call_chain = [self.call_chain.clone(), vec![Rc::from(name)]].concat();
+        memory.clock = self.clock.clone();
+        memory
+    }
+
+    pub fn is_recurrence(&self, name: &str) -> bool {
+        self.recurrences.iter().any(|(n, _)| n == name)
     }
 
     pub fn get_history(&self, name: String) -> SharedHistory {
-        self.cells.get(&name).unwrap().clone()
+        match self.cells.get(&name) {
+            Some(history) => history.clone(),
+            None => runtime_error!("{} is not defined", name),
+        }
+    }
+
+    // moves the clock on, for a value written now
+    fn stamp(&mut self) -> Stamp {
+        self.clock.set(self.clock.get() + 1);
+        Stamp {
+            iteration: self.iteration,
+            program: self.call_chain.last().cloned().unwrap_or(Rc::from("")),
+            time: self.clock.get(),
+        }
     }
 
     pub fn update_history(&mut self, name: String, value: VariableType) {
-        self.cells
-            .entry(name.clone())
-            .and_modify(|ent| (**ent).borrow_mut().add(value.clone()) )// (*ent).borrow_mut().add(value.clone()))
-            .or_insert(History::alloc(name, value));
+        let stamp = self.stamp();
+        // derived histories and on change blocks watching this one run once the statement is done
+        if self.dependents.contains_key(&name) {
+            self.changed.push(name.clone());
+        }
+        if !self.cells.contains_key(&name) {
+            self.record(Change::Bound(name.clone(), None));
+        }
+        let history = self
+            .cells
+            .entry(name)
+            .or_insert_with(|| Rc::new(RefCell::new(History::new())))
+            .clone();
+        history.borrow_mut().add_stamped(value, stamp);
+        self.record(Change::Appended(history));
     }
  
     pub fn insert_history(&mut self, name: String, history: Rc<RefCell<History>>) {
-        self.cells.insert(name, history);
+        let old = self.cells.insert(name.clone(), history);
+        self.record(Change::Bound(name, old));
     }
 
+    // replaces the values of destination with those of source. anything sharing destination
+    // (e.g. a caller passing it as a ref parameter) sees the new values, but later changes to
+    // either history don't affect the other
     pub fn copy(&mut self, source: String, destination: String) {
         let source_history: SharedHistory = self.get_history(source);
+        if let Some(ent) = self.cells.get(&destination) {
+            if Rc::ptr_eq(ent, &source_history) {
+                return; // already the same history
+            }
+        }
+        // the values are written now, so they get new stamps
+        let stamp = self.stamp();
+        let copy: History = source_history.borrow_mut().copy(stamp);
+        self.replace(destination, copy);
+    }
+
+    // gives destination the values of history, in place if it exists so anything sharing it sees them
+    pub fn replace(&mut self, destination: String, history: History) {
+        if self.dependents.contains_key(&destination) {
+            self.changed.push(destination.clone());
+        }
+        match self.cells.get(&destination).cloned() {
+            Some(ent) => {
+                let old = std::mem::replace(&mut *ent.borrow_mut(), history);
+                self.record(Change::Replaced(ent, old));
+            }
+            None => self.insert_history(destination, Rc::new(RefCell::new(history))),
+        }
+    }
+
+    // keeps the values of a history that something other than this memory may change, such as
+    // a program it is passed to as a ref parameter, so a try or atomic block can put them back
+    pub fn save_history(&mut self, history: &SharedHistory) {
+        if !self.journal.is_empty() {
+            let old = history.borrow().clone();
+            self.record(Change::Replaced(history.clone(), old));
+        }
+    }
+
+    fn record(&mut self, change: Change) {
+        if let Some(changes) = self.journal.last_mut() {
+            changes.push(change);
+        }
+    }
+
+    pub fn begin_journal(&mut self) {
+        self.journal.push(Vec::new());
+    }
+
+    // keeps the changes of the innermost block, which an outer block can still undo
+    pub fn commit_journal(&mut self) {
+        let changes = self.journal.pop().unwrap();
+        if let Some(outer) = self.journal.last_mut() {
+            outer.extend(changes);
+        }
+    }
+
+    // undoes the changes of the innermost block, last first
+    pub fn rollback_journal(&mut self) {
+        let changes = self.journal.pop().unwrap();
+        for change in changes.into_iter().rev() {
+            match change {
+                Change::Appended(history) => history.borrow_mut().pop(),
+                Change::Replaced(history, old) => *history.borrow_mut() = old,
+                Change::Bound(name, Some(old)) => {
+                    self.cells.insert(name, old);
+                }
+                Change::Bound(name, None) => {
+                    self.cells.remove(&name);
+                }
+            }
+        }
+    }
 
-        self.cells
-            .entry(destination)
-            .and_modify(|ent| *ent = source_history.clone())
-            .or_insert(source_history);
+    // makes destination another name for source, so a change through either is seen by both
+    pub fn alias(&mut self, source: String, destination: String) {
+        let source_history: SharedHistory = self.get_history(source);
+        self.insert_history(destination, source_history);
     }
 }
@@ -0,0 +1,14 @@
+// programs built into the binary, loaded with run "std:name" or use "std:name" as name
+
+pub fn source(name: &str) -> Option<&'static str> {
+    match name {
+        "max" => Some(include_str!("stdlib/max.sq")),
+        "min" => Some(include_str!("stdlib/min.sq")),
+        "next_greater" => Some(include_str!("stdlib/next_greater.sq")),
+        "reverse" => Some(include_str!("stdlib/reverse.sq")),
+        "search" => Some(include_str!("stdlib/search.sq")),
+        "sort" => Some(include_str!("stdlib/sort.sq")),
+        "statistics" => Some(include_str!("stdlib/statistics.sq")),
+        _ => None,
+    }
+}
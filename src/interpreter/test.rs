@@ -38,3 +38,703 @@ fn test_next_greater() {
 
     assert_out(output, "4");
 }
+
+#[test]
+fn test_frequency() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/frequency.sq", "{3, 1, 3, 2, 3}"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "{1: 1, 2: 1, 3: 3}");
+}
+
+#[test]
+fn test_keys() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/keys.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3 c \na 1 \nb 2 \n");
+}
+
+#[test]
+fn test_use_constants() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/circle.sq", "{2}"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "12.56636 12.56636");
+}
+
+#[test]
+fn test_unary_minus() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/sinx.sq"])
+        .output()
+        .unwrap();
+
+    assert_out(
+        output,
+        "approx: 4 -6.666666666666666 1.8666666666666671 -1.3841269841269837 -0.6617283950617279 \
+         -0.7668045534712196 -0.7560275115830666 -0.7568486195364497 -0.7568003190686036",
+    );
+
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/errors/negate_overflow.sq"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "integer overflow: -(-9223372036854775808)\n"
+    );
+}
+
+#[test]
+fn test_sort_repeated_and_negative() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/sort.sq", "{3, -1, 2, 3, -5, 2}"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "sorted: -5 -1 2 2 3 3");
+}
+
+#[test]
+fn test_std_programs() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/std_programs.sq", "{3, -1, 2, 3, -5, 2}"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "sorted: 0 -5 -1 2 2 3 3 \nreversed: 0 2 -5 3 2 -1 3 \nlargest: 0 3 \nsmallest: 0 -5 \nindex: 0 2 \n"
+    );
+}
+
+#[test]
+fn test_std_programs_empty() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/std_empty.sq"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "1 1 1 1 -1 {\"count\": 0, \"sum\": 0}");
+}
+
+#[test]
+fn test_std_statistics() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/statistics.sq", "{1, 2, 3, 4}"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "{\"count\": 4, \"max\": 4, \"mean\": 2.5, \"min\": 1, \"sum\": 10}");
+}
+
+#[test]
+fn test_include_dir() {
+    let output = Command::new("target/debug/sequence")
+        .args(["-I", "examples", "examples/include/area.sq", "{2}"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "12.56636");
+}
+
+#[test]
+fn test_sequence_path() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/include/area.sq", "{2}"])
+        .env("SEQUENCE_PATH", "examples")
+        .output()
+        .unwrap();
+
+    assert_out(output, "12.56636");
+}
+
+#[test]
+fn test_run_result() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/sum_squares.sq", "{1, 2, 3}"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "14");
+}
+
+#[test]
+fn test_run_value_arguments() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/window_max.sq", "{9, 1, 2, 8, 3, 4}"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "8 10");
+}
+
+#[test]
+fn test_named_parameters() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/add_default.sq", "b={4}", "a={1}", "verbose={true}"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "1 4");
+}
+
+#[test]
+fn test_default_parameters() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/add_named.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "10 \n12 \n");
+}
+
+#[test]
+fn test_parameter_modes() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/pass_modes.sq"])
+        .output()
+        .unwrap();
+
+    assert_out(output, "3 11");
+}
+
+#[test]
+fn test_const_passed_on_as_copy() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/const_pass.sq", "{1}"])
+        .output()
+        .unwrap();
+
+    // bump.sq shares its parameter by default, but only gets a copy of a const one
+    assert_out(output, "c: 1");
+}
+
+#[test]
+fn test_copy_and_alias() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/copy.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a: 1 2 3 4 \nb: 1 2 5 \nc: 1 2 3 4 \n"
+    );
+}
+
+#[test]
+fn test_run_persistent() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/persistent.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a: 1 2 3 \nb: 10 20 30 \nc: 1 1 1 \n"
+    );
+}
+
+#[test]
+fn test_nested_expect_all() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/errors/nested_expect_all.sq"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "line 2: expect is only allowed at the top level of a file\n"
+    );
+}
+
+#[test]
+fn test_keywords_as_names() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/keyword_names.sq"])
+        .output()
+        .unwrap();
+    assert_out(output, "4 8 3 4 3.5 3");
+
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/errors/reserved_name.sq"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "line 2: `derive` is a reserved word\n");
+}
+
+#[test]
+fn test_generator() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/generator.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "f: 0 1 1 2 3 5 8 13 \n"
+    );
+}
+
+#[test]
+fn test_generator_ends_without_yielding() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/countdown_next.sq", "{3}"])
+        .output()
+        .unwrap();
+    assert_out(output, "x: 3 2 1 1");
+
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/countdown_next.sq", "{0}"])
+        .output()
+        .unwrap();
+    assert_out(output, "x: false");
+}
+
+#[test]
+fn test_recursive_run() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/run_factorial.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3628800 \n");
+
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/parity.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "false true \n");
+}
+
+#[test]
+fn test_max_depth() {
+    let output = Command::new("target/debug/sequence")
+        .args(["--max-depth", "5", "examples/countdown.sq", "{0}"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0 \n1 \n2 \n3 \n4 \n");
+    let countdown = "examples/countdown.sq";
+    let chain = [countdown; 6].join(" -> ");
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains(&format!("programs ran each other more than 5 deep: {}", chain)));
+}
+
+#[test]
+fn test_max_depth_deeper_than_the_main_stack() {
+    let output = Command::new("target/debug/sequence")
+        .args(["--max-depth", "2000", "examples/countdown.sq", "{0}"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("programs ran each other more than 2000 deep"));
+    assert!(!stderr.contains("overflowed its stack"));
+
+    let output = Command::new("target/debug/sequence")
+        .args(["--max-depth", "10001", "examples/countdown.sq", "{0}"])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("Usage:"));
+}
+
+#[test]
+fn test_same_file_name_in_different_directories() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/paths.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "first \nsecond \n");
+}
+
+#[test]
+fn test_expect_all_and_finally() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/finally.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "three \nat least three \n3 6 \n"
+    );
+}
+
+#[test]
+fn test_require() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/require.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "7 91 \n");
+}
+
+#[test]
+fn test_temporal_conditions() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/temporal.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "seen: false false true true true true \n\
+         positive: true true true false false false \n\
+         after: false true true false false true \n"
+    );
+
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/settle.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1.414213562373095 8 \n");
+}
+
+#[test]
+fn test_temporal_condition_after_short_circuit() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/temporal_short_circuit.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "error got too large \n6 \n");
+}
+
+#[test]
+fn test_meta_values() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/meta.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "examples/meta.sq 0 0 \nexamples/meta.sq 0 1 \n\
+         examples/meta.sq 1 0 \nexamples/meta.sq 1 1 \n\
+         examples/meta.sq 2 0 \nexamples/meta.sq 2 1 \n"
+    );
+}
+
+#[test]
+fn test_at_iteration() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/at.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "11 4 1 6 \n1 3 examples/at.sq \n"
+    );
+}
+
+#[test]
+fn test_at_iteration_after_copy() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/at_copy.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "6 4 0 5 6 \n");
+}
+
+#[test]
+fn test_prev_first_delta_changed() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/steps.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "-1 1 1 true \n1 1 2 false \n2 1 4 true \n4 1 8 true \n1 1 \n"
+    );
+}
+
+#[test]
+fn test_first_of_empty_history() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/errors/first_empty.sq"])
+        .output()
+        .unwrap();
+
+    // a runtime error is reported as its message alone
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "first a: a has no values\n");
+}
+
+#[test]
+fn test_keys_of_empty_map() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/keys_empty.sq"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0 false \n1 \n");
+
+    // reading a value of the empty history is an error, not a crash
+    for (file, message) in [
+        ("examples/errors/empty_last.sq", "b has no values\n"),
+        ("examples/errors/empty_window.sq", "mean(window b 3): b has no values\n"),
+    ] {
+        let output = Command::new("target/debug/sequence").args([file]).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), message);
+    }
+}
+
+#[test]
+fn test_window_statistics() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/window.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 5 1 4 \n0 5 0 4 \n3 4 0 3 \n4 7 0 4 \n1 8 1 4 \n0 5 0 4 \n3 4 0 3 \n2 1.5811388300841898 \n"
+    );
+}
+
+#[test]
+fn test_window_stddev_of_large_values() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/window_large.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0.5 100000000 \n");
+}
+
+#[test]
+fn test_converged() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/sinx_converged.sq"])
+        .output()
+        .unwrap();
+
+    // stops at the first step of at most 0.0001
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let approx: Vec<f64> = stdout.split_whitespace().skip(1).map(|x| x.parse().unwrap()).collect();
+    let steps: Vec<f64> = approx.windows(2).map(|pair| (pair[1] - pair[0]).abs()).collect();
+    assert_eq!(approx.len(), 9);
+    assert!(steps[steps.len() - 1] <= 0.0001);
+    assert!(steps[..steps.len() - 1].iter().all(|step| *step > 0.0001));
+}
+
+#[test]
+fn test_oscillating() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/collatz_cycle.sq"])
+        .output()
+        .unwrap();
+
+    // 4 coming back is not enough, it takes the whole cycle twice
+    assert_out(output, "a: 6 3 10 5 16 8 4 2 1 4 2 1");
+}
+
+#[test]
+fn test_recurrence() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/recurrence.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 0 \n1 1 \n2 3 \n3 6 \n55 6 \nfib: 0 1 1 2 3 5 \ntotal: 0 1 3 6 11 \n"
+    );
+}
+
+#[test]
+fn test_recurrence_errors() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/errors/recurrence_ahead.sq"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("5::$total is past the end of total, which has 1 values"));
+
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/errors/negative_index.sq"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("-2::$fib: an index can't be negative"));
+}
+
+#[test]
+fn test_derive_and_on_change() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/derive.sq"])
+        .output()
+        .unwrap();
+
+    // derived histories are recomputed before the on change blocks of what they derive from run
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a 1 \ntotal 11 22 \ntotal 12 24 \na 2 \ntotal 13 26 \na 3 \ntotal 23 46 \ntotal 24 48 \na 4 \n\
+         total: 11 12 13 23 24 \ndouble: 22 24 26 46 48 \n"
+    );
+}
+
+#[test]
+fn test_derive_and_on_change_after_replace() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/derive_replace.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "b 2 2 \nc 2 2 \nb 2 2 \nc 3 3 \nlast: 20 20 \n"
+    );
+}
+
+#[test]
+fn test_fork() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/fork.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a: 1 2 3 4 5 50 500 \nb: 1 2 3 4 104 204 \nc: 1 2 \n3 1 1 6 \ncommon: 1 2 3 4 \n"
+    );
+}
+
+#[test]
+fn test_fork_many_times() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/fork_deep.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0 500 999 249 250 \n");
+}
+
+#[test]
+fn test_try_and_atomic() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/rollback.sq"])
+        .output()
+        .unwrap();
+
+    // the iterations where a reaches 4 roll back, and every atomic block from then on divides by zero
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a: 0 1 2 3 \ntotal: 0 1 5 14 \nlast: 1 2 3 \nratio: 6 12 \ncount: 2 3 \nhead: 0 \n"
+    );
+    // the errors are caught by the blocks, so none of them is reported
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}
+
+#[test]
+fn test_persistent_run_rolled_back() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/persistent_atomic.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "r: 1 3 \n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}
+
+#[test]
+fn test_generator_rolled_back() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/generator_rollback.sq"])
+        .output()
+        .unwrap();
+
+    // the values the generator appended to h in the rolled back step are taken back
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "h: 0 1 3 \nx: 0 1 3 \n");
+}
+
+#[test]
+fn test_invariant() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/invariant.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 2 2 \n2 4 6 \n3 4 10 \n4 8 18 \n5 16 34 \n6 16 50 \n"
+    );
+    let report = "examples/invariant.sq: invariant (i <= #a) and (|(delta total) < 50) does not hold \
+                  after iteration 5\n  i: ... 2 3 4 5 6\n  a: 1 2 4 8 16\n  total: ... 6 10 18 34 50\n";
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), report);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_invariant_not_rolled_back() {
+    let output = Command::new("target/debug/sequence")
+        .args(["examples/invariant_atomic.sq"])
+        .output()
+        .unwrap();
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "examples/bounded.sq: invariant n < 3 does not hold after iteration 2\n  n: 0 1 2 3\n"
+    );
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_test_runner() {
+    let output = Command::new("target/debug/sequence")
+        .args(["test", "examples"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "ok   examples/tests/fib.sq: fib starts with its seeds\n\
+         ok   examples/tests/fib.sq: fib adds the last two values\n\
+         ok   examples/tests/fib.sq: factorial runs on its own\n\
+         3 passed, 0 failed\n"
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    // a file that doesn't parse or runs a missing file fails as a whole, the rest are still tested
+    let output = Command::new("target/debug/sequence")
+        .args(["test", "tests/fixtures"])
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "FAIL tests/fixtures/broken.sq\n  \
+         line 2: expected RPAREN got NEWLINE\n\
+         ok   tests/fixtures/failing.sq: sums up\n\
+         FAIL tests/fixtures/failing.sq: doubles\n  \
+         assert a == 8 failed: three doublings\n    a: 1 2 4\n\
+         FAIL tests/fixtures/missing.sq\n  \
+         line 2: could not find file: nowhere.sq\n\
+         1 passed, 3 failed\n"
+    );
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use super::runtime_types::{History, VariableType};
+
+// the last `size` values of a history, kept up to date as values are appended so the
+// statistics over it don't have to look at every value again each iteration
+pub struct Window {
+    size: usize,
+    seen: usize,                       // how many values of the history have been added
+    last: Option<(u64, VariableType)>, // stamp time and value of the last one, to notice a replaced history
+    count: usize, // values in the window, fewer than size while the history is shorter
+    sum: f64,
+    mean: f64,
+    deviations: f64, // sum of squared differences from the mean, updated as in Welford's method
+    int_sum: i64,
+    floats: usize,             // floats in the window, the sum is an integer when there are none
+    smallest: VecDeque<usize>, // indexes of values that can still be the smallest, increasing in value
+    largest: VecDeque<usize>,  // the same for the largest, decreasing in value
+}
+
+impl Window {
+    pub fn new(size: usize) -> Window {
+        Window {
+            size,
+            seen: 0,
+            last: None,
+            count: 0,
+            sum: 0.0,
+            mean: 0.0,
+            deviations: 0.0,
+            int_sum: 0,
+            floats: 0,
+            smallest: VecDeque::new(),
+            largest: VecDeque::new(),
+        }
+    }
+
+    // whether the window can carry on from where it was for this history, which only holds
+    // if values were appended to it since the last update
+    pub fn follows(&self, history: &History, size: usize) -> bool {
+        if size != self.size || history.len() < self.seen {
+            return false;
+        }
+        match &self.last {
+            Some((time, value)) => {
+                history.get_stamp(self.seen - 1).time == *time
+                    && history.get_past(self.seen - 1) == *value
+            }
+            None => true,
+        }
+    }
+
+    // adds the values appended since the last update, dropping the ones that fell out of the window
+    pub fn update(&mut self, history: &History) {
+        // values before the last size can't end up in the window, so they aren't looked at
+        let from = self.seen.max(history.len().saturating_sub(self.size));
+        if from > self.seen {
+            *self = Window::new(self.size);
+        }
+        for index in from..history.len() {
+            self.add(&history.get_past(index));
+            if self.count > self.size {
+                self.remove(&history.get_past(index - self.size));
+            }
+
+            let value = number(&history.get_past(index));
+            while self.smallest.back().is_some_and(|i| number(&history.get_past(*i)) >= value) {
+                self.smallest.pop_back();
+            }
+            self.smallest.push_back(index);
+            while self.largest.back().is_some_and(|i| number(&history.get_past(*i)) <= value) {
+                self.largest.pop_back();
+            }
+            self.largest.push_back(index);
+        }
+        self.seen = history.len();
+
+        let start = self.start();
+        while self.smallest.front().is_some_and(|i| *i < start) {
+            self.smallest.pop_front();
+        }
+        while self.largest.front().is_some_and(|i| *i < start) {
+            self.largest.pop_front();
+        }
+        self.last = Some((
+            history.get_stamp(self.seen - 1).time,
+            history.get_past(self.seen - 1),
+        ));
+    }
+
+    fn add(&mut self, value: &VariableType) {
+        let x = number(value);
+        self.count += 1;
+        self.sum += x;
+        let old_mean = self.mean;
+        self.mean += (x - old_mean) / self.count as f64;
+        self.deviations += (x - old_mean) * (x - self.mean);
+        match value {
+            VariableType::FLOAT(_) => self.floats += 1,
+            _ => self.int_sum += x as i64,
+        }
+    }
+
+    fn remove(&mut self, value: &VariableType) {
+        let x = number(value);
+        self.count -= 1;
+        self.sum -= x;
+        let old_mean = self.mean;
+        self.mean -= (x - old_mean) / self.count as f64;
+        self.deviations -= (x - old_mean) * (x - self.mean);
+        match value {
+            VariableType::FLOAT(_) => self.floats -= 1,
+            _ => self.int_sum -= x as i64,
+        }
+    }
+
+    // index of the first value in the window
+    fn start(&self) -> usize {
+        self.seen - self.count
+    }
+
+    pub fn sum(&self) -> VariableType {
+        if self.floats == 0 {
+            return VariableType::INTEGER(self.int_sum);
+        }
+        VariableType::FLOAT(self.sum)
+    }
+
+    pub fn mean(&self) -> VariableType {
+        VariableType::FLOAT(self.mean)
+    }
+
+    // the population standard deviation of the values in the window
+    pub fn stddev(&self) -> VariableType {
+        let variance = self.deviations / self.count as f64;
+        VariableType::FLOAT(variance.max(0.0).sqrt())
+    }
+
+    pub fn min(&self, history: &History) -> VariableType {
+        history.get_past(*self.smallest.front().unwrap())
+    }
+
+    pub fn max(&self, history: &History) -> VariableType {
+        history.get_past(*self.largest.front().unwrap())
+    }
+}
+
+pub fn number(value: &VariableType) -> f64 {
+    match value.clone().bool_to_number() {
+        VariableType::INTEGER(x) => x as f64,
+        VariableType::FLOAT(x) => x,
+        x => runtime_error!("expected a number, got {}", x),
+    }
+}
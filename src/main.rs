@@ -2,10 +2,18 @@ mod interpreter;
 mod user_options;
 mod command_line;
 
-use std::{env, fs, process};
+use std::{env, fs, process, thread};
 use std::path::PathBuf;
 
-use crate::interpreter::executor::run_program;
+use crate::interpreter::executor::{preload, report_errors, run_program, run_tests};
+use crate::interpreter::parser::statement::ProgramCache;
+use crate::interpreter::runtime_types::error_message;
+use crate::command_line::ArgResult;
+use crate::user_options::USER_OPTIONS;
+
+// the stack the interpreter starts with, and what it needs for each program running another
+const BASE_STACK: usize = 8 * 1024 * 1024;
+const STACK_PER_RUN: usize = 64 * 1024;
 
 fn main() {
     // TODO:
@@ -16,7 +24,30 @@ fn main() {
     // [] add an option print if the a file is running at the top level
 
     let result = command_line::handle_args(&env::args().collect());
-    
+
+    // the interpreter runs on a thread with enough stack for programs to run each other
+    // as deep as --max-depth allows
+    report_errors();
+    let stack_size = BASE_STACK + USER_OPTIONS.lock().unwrap().max_depth * STACK_PER_RUN;
+    let interpreter = thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || run(result))
+        .unwrap();
+    // the error has been reported already
+    match interpreter.join() {
+        Err(error) if error_message(error.as_ref()).is_some() => process::exit(1),
+        Err(_) => process::exit(101),
+        Ok(()) => {}
+    }
+}
+
+fn run(result: ArgResult) {
+    if let Some(dir) = &result.test_dir {
+        if !run_tests(&PathBuf::from(dir)) {
+            process::exit(1);
+        }
+        return;
+    }
     
     let buf = fs::read_to_string(&result.file_name).unwrap_or_else(|_| {
         eprintln!("could not read file: {}", &result.file_name);
@@ -25,5 +56,7 @@ fn main() {
 
     let path = PathBuf::from(&result.file_name);
 
-    run_program(&buf, &path, result.parameters);
+    let mut programs = ProgramCache::new();
+    preload(&mut programs, &path, &buf);
+    run_program(programs, &path, command_line::get_parameters(&result.parameters));
 }
@@ -1,7 +1,17 @@
+use std::path::PathBuf;
 use std::sync::Mutex;
 
 pub struct Options {
     pub debug: bool,
+    pub include_dirs: Vec<PathBuf>, // from -I, searched after the directory of the current file
+    pub max_depth: usize, // how deep programs can run each other before it's an error
 }
 
-pub static USER_OPTIONS: Mutex<Options> = Mutex::new(Options { debug: false });
+// the deepest --max-depth can go, the interpreter's stack is sized for it in main
+pub const MAX_DEPTH_LIMIT: usize = 10_000;
+
+pub static USER_OPTIONS: Mutex<Options> = Mutex::new(Options {
+    debug: false,
+    include_dirs: Vec::new(),
+    max_depth: 200,
+});